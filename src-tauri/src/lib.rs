@@ -1,8 +1,9 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use base64::{Engine as _, engine::general_purpose};
 
 /// Get the app's data directory (AppData/Local/{bundle_id}/paper-reader-data on Windows)
+#[tracing::instrument(skip(app))]
 fn get_data_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
     let base = app
         .path()
@@ -16,231 +17,7594 @@ fn get_data_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
     Ok(data_dir)
 }
 
+/// Holds the passphrase-derived key while the library is unlocked.
+///
+/// The lock only gates the app's own commands. It is not a security boundary:
+/// files stay readable on disk and through the asset protocol, and removing
+/// `.master_passphrase.json` lifts it.
+#[derive(Default)]
+struct DataLock {
+    key: std::sync::Mutex<Option<[u8; 32]>>,
+}
+
+const MASTER_PASSPHRASE_FILE: &str = ".master_passphrase.json";
+
+/// Error returned by gated commands while a passphrase is set but not entered
+const LOCKED_ERROR: &str = "Locked";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MasterPassphrase {
+    /// Argon2 PHC string used to verify the passphrase
+    verifier: String,
+    /// Separate salt for deriving the in-memory key, so it never matches the verifier
+    key_salt: String,
+}
+
+/// Fail with `LOCKED_ERROR` when a master passphrase is set and hasn't been entered
+fn ensure_unlocked(app: &tauri::AppHandle) -> Result<(), String> {
+    let data_dir = get_data_dir(app)?;
+    if !data_dir.join(MASTER_PASSPHRASE_FILE).exists() {
+        return Ok(());
+    }
+    let key = app.state::<DataLock>().inner().key.lock().unwrap_or_else(|e| e.into_inner());
+    if key.is_some() { Ok(()) } else { Err(LOCKED_ERROR.to_string()) }
+}
+
+fn derive_data_key(passphrase: &str, salt: &str) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt.as_bytes(), &mut key)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+/// Set or change the master passphrase. Changing it requires the library to be
+/// unlocked; afterwards it stays unlocked under the new passphrase.
+#[tauri::command]
+#[tracing::instrument(skip(app, passphrase), err)]
+fn set_master_passphrase(app: tauri::AppHandle, passphrase: String) -> Result<(), String> {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+    ensure_unlocked(&app)?;
+    if passphrase.is_empty() {
+        return Err("Passphrase cannot be empty".to_string());
+    }
+    let data_dir = get_data_dir(&app)?;
+    let verifier = argon2::Argon2::default()
+        .hash_password(passphrase.as_bytes(), &SaltString::generate(&mut OsRng))
+        .map_err(|e| format!("Failed to hash passphrase: {}", e))?
+        .to_string();
+    let key_salt = SaltString::generate(&mut OsRng).as_str().to_string();
+    let key = derive_data_key(&passphrase, &key_salt)?;
+
+    let json = serde_json::to_string_pretty(&MasterPassphrase { verifier, key_salt })
+        .map_err(|e| format!("Failed to serialize passphrase settings: {}", e))?;
+    atomic_write(&data_dir.join(MASTER_PASSPHRASE_FILE), json.as_bytes())
+        .map_err(|e| format!("Failed to save passphrase settings: {}", e))?;
+    *app.state::<DataLock>().inner().key.lock().unwrap_or_else(|e| e.into_inner()) = Some(key);
+    Ok(())
+}
+
+/// Check the passphrase and unlock the gated commands until the app exits
+#[tauri::command]
+#[tracing::instrument(skip(app, passphrase), err)]
+fn unlock_data(app: tauri::AppHandle, passphrase: String) -> Result<(), String> {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+    let data_dir = get_data_dir(&app)?;
+    let path = data_dir.join(MASTER_PASSPHRASE_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read passphrase settings: {}", e))?;
+    let settings: MasterPassphrase = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid passphrase settings: {}", e))?;
+    let verifier = PasswordHash::new(&settings.verifier)
+        .map_err(|e| format!("Invalid passphrase settings: {}", e))?;
+    argon2::Argon2::default()
+        .verify_password(passphrase.as_bytes(), &verifier)
+        .map_err(|_| "Incorrect passphrase".to_string())?;
+    let key = derive_data_key(&passphrase, &settings.key_salt)?;
+    *app.state::<DataLock>().inner().key.lock().unwrap_or_else(|e| e.into_inner()) = Some(key);
+    Ok(())
+}
+
+/// Forget the entered passphrase, so gated commands fail with `LOCKED_ERROR` again
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn lock_data(app: tauri::AppHandle) {
+    *app.state::<DataLock>().inner().key.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Counts in-flight write commands so shutdown can wait for them to finish
+/// instead of leaving partially written files behind
+#[derive(Default)]
+struct PendingWrites {
+    count: std::sync::Mutex<usize>,
+    idle: std::sync::Condvar,
+}
+
+struct PendingWriteGuard<'a>(&'a PendingWrites);
+
+impl PendingWrites {
+    fn begin(&self) -> PendingWriteGuard<'_> {
+        *self.count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        PendingWriteGuard(self)
+    }
+
+    /// Block until no writes are in flight; returns false on timeout
+    fn wait_idle(&self, timeout: std::time::Duration) -> bool {
+        let count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        let (_count, result) = self
+            .idle
+            .wait_timeout_while(count, timeout, |n| *n > 0)
+            .unwrap_or_else(|e| e.into_inner());
+        !result.timed_out()
+    }
+}
+
+impl Drop for PendingWriteGuard<'_> {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap_or_else(|e| e.into_inner());
+        *count -= 1;
+        if *count == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+/// How long shutdown waits for in-flight writes before giving up
+const SHUTDOWN_WRITE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+fn flush_pending_writes(app: &tauri::AppHandle) {
+    if !app.state::<PendingWrites>().wait_idle(SHUTDOWN_WRITE_TIMEOUT) {
+        log::warn!("[shutdown] Timed out waiting for pending writes");
+    }
+}
+
+/// Caps how many CPU-heavy jobs (PDF rendering, image analysis, hashing) run at
+/// once; further jobs wait for a free slot instead of piling onto the CPU
+struct JobLimiter {
+    /// (running jobs, max concurrent jobs)
+    slots: std::sync::Mutex<(usize, usize)>,
+    available: std::sync::Condvar,
+}
+
+struct JobPermit<'a>(&'a JobLimiter);
+
+impl Default for JobLimiter {
+    fn default() -> Self {
+        let max = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+        JobLimiter { slots: std::sync::Mutex::new((0, max)), available: std::sync::Condvar::new() }
+    }
+}
+
+impl JobLimiter {
+    fn acquire(&self) -> JobPermit<'_> {
+        let slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let mut slots = self
+            .available
+            .wait_while(slots, |(running, max)| *running >= *max)
+            .unwrap_or_else(|e| e.into_inner());
+        slots.0 += 1;
+        JobPermit(self)
+    }
+
+    fn max(&self) -> usize {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).1
+    }
+
+    fn set_max(&self, max: usize) {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).1 = max;
+        // Raising the limit may free slots for several waiters at once
+        self.available.notify_all();
+    }
+}
+
+impl Drop for JobPermit<'_> {
+    fn drop(&mut self) {
+        self.0.slots.lock().unwrap_or_else(|e| e.into_inner()).0 -= 1;
+        self.0.available.notify_one();
+    }
+}
+
+/// Run an expensive job on the blocking pool once the limiter has a free slot
+async fn run_limited<T, F>(app: &tauri::AppHandle, job: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let _permit = app.state::<JobLimiter>().inner().acquire();
+        job()
+    })
+    .await
+    .map_err(|e| format!("Background job failed: {}", e))?
+}
+
+/// Change how many expensive jobs may run concurrently; queued jobs pick up the new limit
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn set_max_concurrent_jobs(app: tauri::AppHandle, n: usize) -> Result<(), String> {
+    if n == 0 {
+        return Err("Job limit must be at least 1".to_string());
+    }
+    app.state::<JobLimiter>().set_max(n);
+    Ok(())
+}
+
+/// Write bytes to a temp file next to `path`, then rename it over the target.
+/// A failed or interrupted write never leaves a truncated file at `path`.
+fn atomic_write(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    write_via_temp(path, bytes, false)
+}
+
+/// `atomic_write` that also survives power loss: the temp file is fsynced before
+/// the rename and the directory after it, so the new content is on disk when
+/// this returns. Each fsync waits for the device, typically milliseconds on an
+/// SSD and far longer on spinning or network disks, so keep it to writes that
+/// matter rather than every autosave.
+fn atomic_write_durable(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    write_via_temp(path, bytes, true)
+}
+
+fn write_via_temp(path: &Path, bytes: &[u8], durable: bool) -> std::io::Result<()> {
+    use std::io::Write;
+
+    static TEMP_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    let parent = path
+        .parent()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no parent"))?;
+    fs::create_dir_all(parent)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?
+        .to_string_lossy();
+    let seq = TEMP_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let tmp = parent.join(format!(".{}.{}-{}.tmp", name, std::process::id(), seq));
+
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(bytes)?;
+        if durable {
+            file.sync_all()?;
+        }
+        drop(file);
+        fs::rename(&tmp, path)
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // The rename itself lives in the directory entry. Windows can't open a
+    // directory as a file; NTFS journals the rename instead.
+    #[cfg(unix)]
+    if durable {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Fail up front with `NoSpace` if the volume holding `dir` can't fit `needed` bytes
+fn ensure_free_space(dir: &Path, needed: u64) -> Result<(), String> {
+    let available = fs4::available_space(dir)
+        .map_err(|e| format!("Failed to query free space: {}", e))?;
+    if needed > available {
+        return Err(format!(
+            "NoSpace: writing {} bytes needs more than the {} bytes available",
+            needed, available
+        ));
+    }
+    Ok(())
+}
+
+/// Sidecar recording what kind of file an import is (paper, note, annotation, asset)
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FileMeta {
+    #[serde(rename = "type")]
+    file_type: String,
+    imported_at: u64,
+}
+
+fn meta_sidecar_path(data_dir: &Path, filename: &str) -> PathBuf {
+    data_dir.join(format!("{}.meta.json", filename))
+}
+
+#[derive(serde::Serialize)]
+struct CopyResult {
+    filename: String,
+    /// The same file was imported before; `filename` is the earlier copy and nothing was copied
+    was_duplicate: bool,
+    /// First-page JPEG for imported PDFs, when thumbnails are enabled and rendering worked
+    thumbnail_filename: Option<String>,
+}
+
+/// Longest edge of a thumbnail, in pixels
+const THUMBNAIL_SIZE: i32 = 256;
+
+/// `<stem>_thumb.jpg` next to `filename`
+fn thumbnail_filename(filename: &str) -> String {
+    let stem = Path::new(filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match filename.rfind(['/', '\\']) {
+        Some(slash) => format!("{}/{}_thumb.jpg", &filename[..slash], stem),
+        None => format!("{}_thumb.jpg", stem),
+    }
+}
+
+/// Render the first page of a PDF in the data dir to `<stem>_thumb.jpg`, fitted
+/// within `THUMBNAIL_SIZE` square. Non-PDFs and render failures give `None`.
+#[cfg(feature = "pdf-thumbnails")]
+fn create_pdf_thumbnail(data_dir: &Path, filename: &str) -> Option<String> {
+    if !filename.to_ascii_lowercase().ends_with(".pdf") {
+        return None;
+    }
+    render_pdf_thumbnail(data_dir, filename)
+        .map_err(|e| log::warn!("[copy_file_to_data] No thumbnail for '{}': {}", filename, e))
+        .ok()
+}
+
+#[cfg(feature = "pdf-thumbnails")]
+fn render_pdf_thumbnail(data_dir: &Path, filename: &str) -> Result<String, String> {
+    use pdfium_render::prelude::PdfRenderConfig;
+
+    let pdfium = load_pdfium()?;
+    let document = pdfium
+        .load_pdf_from_file(&data_dir.join(filename), None)
+        .map_err(|e| format!("Failed to open PDF: {}", e))?;
+    let page = document
+        .pages()
+        .first()
+        .map_err(|e| format!("Failed to load first page: {}", e))?;
+    let config = PdfRenderConfig::new()
+        .set_target_width(THUMBNAIL_SIZE)
+        .set_maximum_height(THUMBNAIL_SIZE);
+    let image = page
+        .render_with_config(&config)
+        .and_then(|bitmap| bitmap.as_image())
+        .map_err(|e| format!("Failed to render first page: {}", e))?;
+    save_thumbnail(data_dir, filename, image)
+}
+
+#[cfg(not(feature = "pdf-thumbnails"))]
+fn render_pdf_thumbnail(_data_dir: &Path, _filename: &str) -> Result<String, String> {
+    Err("PDF thumbnails are disabled in this build".to_string())
+}
+
+fn render_image_thumbnail(data_dir: &Path, filename: &str) -> Result<String, String> {
+    let image = open_image(&data_dir.join(filename))?;
+    let size = THUMBNAIL_SIZE as u32;
+    save_thumbnail(data_dir, filename, image.thumbnail(size, size))
+}
+
+fn save_thumbnail(data_dir: &Path, filename: &str, image: image::DynamicImage) -> Result<String, String> {
+    let thumb = thumbnail_filename(filename);
+    let mut bytes = Vec::new();
+    // JPEG has no alpha channel
+    image::DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Jpeg)
+        .map_err(|e| format!("Failed to save thumbnail: {}", e))?;
+    write_within_quota(&data_dir.join(&thumb), None, &bytes, false, true, &thumb)?;
+    Ok(thumb)
+}
+
+/// Images `pregenerate_thumbnails` covers alongside PDFs
+const THUMBNAIL_IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+#[derive(Clone, serde::Serialize)]
+struct ThumbnailProgressEvent {
+    filename: String,
+    /// Files processed so far, including skipped and failed ones
+    done: usize,
+    total: usize,
+}
+
+#[derive(serde::Serialize, Default)]
+struct ThumbnailSummary {
+    generated: usize,
+    /// Already had a thumbnail at least as new as the file
+    skipped: usize,
+    failed: usize,
+}
+
+/// Generate missing or stale thumbnails for every PDF and image in the data dir,
+/// emitting `thumbnail-progress` after each file. Rendering runs on a pool sized
+/// by the job limit (see `set_max_concurrent_jobs`), and each render takes its
+/// own job slot, so together with other expensive jobs it stays within the limit.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn pregenerate_thumbnails(app: tauri::AppHandle) -> Result<ThumbnailSummary, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let workers = app.state::<JobLimiter>().max();
+    let handle = app.clone();
+    // Not run_limited: the workers take a slot per render instead
+    tauri::async_runtime::spawn_blocking(move || pregenerate_thumbnails_in(&handle, &data_dir, workers))
+        .await
+        .map_err(|e| format!("Background job failed: {}", e))?
+}
+
+fn pregenerate_thumbnails_in(
+    app: &tauri::AppHandle,
+    data_dir: &Path,
+    workers: usize,
+) -> Result<ThumbnailSummary, String> {
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tauri::Emitter;
+
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    let sources: Vec<(String, bool)> = files
+        .iter()
+        .filter_map(|path| {
+            let ext = path.extension()?.to_string_lossy().to_lowercase();
+            let is_pdf = ext == "pdf";
+            let is_image = THUMBNAIL_IMAGE_EXTENSIONS.contains(&ext.as_str());
+            let is_thumb = path.file_stem()?.to_string_lossy().ends_with("_thumb");
+            let wanted = if is_pdf { cfg!(feature = "pdf-thumbnails") } else { is_image && !is_thumb };
+            if !wanted {
+                return None;
+            }
+            Some((relative_data_path(data_dir, path).ok()?, is_pdf))
+        })
+        .collect();
+
+    let total = sources.len();
+    let done = AtomicUsize::new(0);
+    let (generated, skipped, failed) = (AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0));
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers.max(1))
+        .build()
+        .map_err(|e| format!("Failed to start thumbnail workers: {}", e))?;
+    pool.install(|| {
+        sources.par_iter().for_each(|(filename, is_pdf)| {
+            let source_modified = fs::metadata(data_dir.join(filename)).map(|m| modified_millis(&m)).unwrap_or(0);
+            let thumb_modified = fs::metadata(data_dir.join(thumbnail_filename(filename)))
+                .ok()
+                .map(|m| modified_millis(&m));
+            if thumb_modified.is_some_and(|t| t >= source_modified) {
+                skipped.fetch_add(1, Ordering::Relaxed);
+            } else {
+                let _permit = app.state::<JobLimiter>().inner().acquire();
+                let rendered = if *is_pdf {
+                    render_pdf_thumbnail(data_dir, filename)
+                } else {
+                    render_image_thumbnail(data_dir, filename)
+                };
+                match rendered {
+                    Ok(_) => generated.fetch_add(1, Ordering::Relaxed),
+                    Err(e) => {
+                        log::warn!("[pregenerate_thumbnails] No thumbnail for '{}': {}", filename, e);
+                        failed.fetch_add(1, Ordering::Relaxed)
+                    }
+                };
+            }
+            let event = ThumbnailProgressEvent {
+                filename: filename.clone(),
+                done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                total,
+            };
+            if let Err(e) = app.emit("thumbnail-progress", event) {
+                log::warn!("[pregenerate_thumbnails] Failed to emit thumbnail-progress: {}", e);
+            }
+        })
+    });
+    Ok(ThumbnailSummary {
+        generated: generated.into_inner(),
+        skipped: skipped.into_inner(),
+        failed: failed.into_inner(),
+    })
+}
+
+#[cfg(not(feature = "pdf-thumbnails"))]
+fn create_pdf_thumbnail(_data_dir: &Path, _filename: &str) -> Option<String> {
+    None
+}
+
+/// Bytes hashed from each end of a file for its import fingerprint
+const FINGERPRINT_SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// Cheap identity for an import source: its size plus a SHA-256 over the first
+/// and last `FINGERPRINT_SAMPLE_BYTES` (the whole file when it is small)
+fn import_fingerprint(path: &Path) -> std::io::Result<String> {
+    use sha2::Digest;
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hasher = sha2::Sha256::new();
+    if size <= 2 * FINGERPRINT_SAMPLE_BYTES {
+        std::io::copy(&mut file, &mut hasher)?;
+    } else {
+        let mut buf = vec![0u8; FINGERPRINT_SAMPLE_BYTES as usize];
+        file.read_exact(&mut buf)?;
+        hasher.update(&buf);
+        file.seek(SeekFrom::End(-(FINGERPRINT_SAMPLE_BYTES as i64)))?;
+        file.read_exact(&mut buf)?;
+        hasher.update(&buf);
+    }
+    Ok(format!("{}-{}", size, hex_encode(&hasher.finalize())))
+}
+
+/// Longest imported filename in bytes: under the usual 255-byte limit with room
+/// for suffixes like `.bak.2`
+const MAX_FILENAME_BYTES: usize = 240;
+
+/// Device names Windows reserves regardless of extension (`nul.txt` included)
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Make one path component valid under Windows rules (the strictest platform
+/// the data dir may be synced to, so they apply everywhere): invalid characters
+/// become `_` and reserved device names get a `_` after the stem, keeping the extension
+fn sanitize_path_component(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_control() || r#"<>:"/\|?*"#.contains(c) { '_' } else { c })
+        .collect();
+    // Windows drops trailing dots and spaces, which would change the name on sync
+    let trimmed = sanitized.trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        return "_".to_string();
+    }
+    let stem_len = trimmed.find('.').unwrap_or(trimmed.len());
+    if WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(&trimmed[..stem_len])) {
+        return format!("{}_{}", &trimmed[..stem_len], &trimmed[stem_len..]);
+    }
+    trimmed.to_string()
+}
+
+/// `sanitize_path_component` applied to every component of an already normalized path
+fn sanitize_relative_path(relative: &Path) -> PathBuf {
+    relative.iter().map(|part| sanitize_path_component(&part.to_string_lossy())).collect()
+}
+
+/// Sanitize each component of a data-dir relative path (`/` or `\` separated).
+/// `.` and `..` are kept as written; write commands go through
+/// `sanitize_write_path`, which rejects paths that escape the data dir.
+fn sanitize_data_path(filename: &str) -> Result<String, String> {
+    if filename.trim().is_empty() {
+        return Err("Filename is empty".to_string());
+    }
+    let components: Vec<String> = filename
+        .split(['/', '\\'])
+        .map(|component| match component {
+            "" | "." | ".." => component.to_string(),
+            _ => sanitize_path_component(component),
+        })
+        .collect();
+    Ok(components.join("/"))
+}
+
+/// The name `write_data_file` and the import commands would store `name` under
+#[tauri::command]
+#[tracing::instrument(err)]
+fn sanitize_filename(name: String) -> Result<String, String> {
+    sanitize_data_path(&name)
+}
+
+/// `sanitize_data_path`, then `resolve_data_path` so `..` and absolute paths are refused
+fn sanitize_write_path(data_dir: &Path, filename: &str) -> Result<String, String> {
+    let filename = sanitize_data_path(filename)?;
+    resolve_data_path(data_dir, &filename)?;
+    Ok(filename)
+}
+
+/// Data-dir filename for an imported file: its own name, sanitized and shortened
+fn import_filename(source: &Path) -> Result<String, String> {
+    let name = source.file_name().ok_or("Invalid filename")?.to_string_lossy();
+    Ok(truncate_long_filename(&sanitize_path_component(&name), MAX_FILENAME_BYTES))
+}
+
+/// Shorten `name` to at most `max_bytes` UTF-8 bytes by cutting the stem at a
+/// character boundary, keeping the extension
+fn truncate_long_filename(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot < max_bytes => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = max_bytes - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], ext)
+}
+
+/// Copy a file into the data dir. A source whose fingerprint matches an earlier
+/// import that is still present returns that file with `was_duplicate` set.
 #[tauri::command]
-fn copy_file_to_data(app: tauri::AppHandle, source_path: String) -> Result<String, String> {
+#[tracing::instrument(skip(app), err)]
+fn copy_file_to_data(
+    app: tauri::AppHandle,
+    source_path: String,
+    file_type: Option<String>,
+) -> Result<CopyResult, String> {
+    ensure_unlocked(&app)?;
     let source = PathBuf::from(&source_path);
     if !source.exists() {
         return Err(format!("Source file does not exist: {}", source_path));
     }
-    let filename = source
-        .file_name()
-        .ok_or("Invalid filename")?
-        .to_string_lossy()
-        .to_string();
+    let filename = import_filename(&source)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let fingerprint = import_fingerprint(&source)
+        .map_err(|e| format!("Failed to fingerprint '{}': {}", source_path, e))?;
+    let conn = open_index(&data_dir)?;
+    let previous: Option<String> = {
+        use rusqlite::OptionalExtension;
+        conn.query_row(
+            "SELECT filename FROM import_history WHERE fingerprint = ?1",
+            [&fingerprint],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to read import history: {}", e))?
+    };
+    if let Some(existing) = previous.filter(|name| data_dir.join(name).is_file()) {
+        log::info!("[copy_file_to_data] '{}' was already imported as '{}'", source_path, existing);
+        let thumb = thumbnail_filename(&existing);
+        let thumbnail_filename = data_dir.join(&thumb).is_file().then_some(thumb);
+        return Ok(CopyResult { filename: existing, was_duplicate: true, thumbnail_filename });
+    }
+
+    copy_within_quota(&source, &data_dir.join(&filename), &filename)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO import_history (fingerprint, filename, imported_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![fingerprint, filename, now_millis() as i64],
+    )
+    .map_err(|e| format!("Failed to record import: {}", e))?;
+    if let Some(file_type) = file_type {
+        let meta = FileMeta { file_type, imported_at: now_millis() };
+        let json = serde_json::to_string_pretty(&meta)
+            .map_err(|e| format!("Failed to serialize file metadata: {}", e))?;
+        write_within_quota(&meta_sidecar_path(&data_dir, &filename), None, json.as_bytes(), false, true, &filename)?;
+    }
+    let thumbnail_filename = create_pdf_thumbnail(&data_dir, &filename);
+    Ok(CopyResult { filename, was_duplicate: false, thumbnail_filename })
+}
+
+/// Downloads `import_url_list` runs at once
+const URL_IMPORT_CONCURRENCY: usize = 4;
+/// Per-request limit, covering connect through the last byte of the body
+const URL_IMPORT_TIMEOUT_SECS: u64 = 60;
+/// Largest download accepted
+const MAX_DOWNLOAD_BYTES: usize = 200 * 1024 * 1024;
+/// Keeps concurrent downloads in separate staging directories
+static DOWNLOAD_STAGING_SEQ: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[derive(serde::Serialize)]
+struct UrlImportResult {
+    /// 1-based data row, not counting the header
+    row: usize,
+    url: String,
+    /// Data-dir filename, when the import worked
+    filename: Option<String>,
+    error: Option<String>,
+}
+
+/// Download every URL (or bare DOI, resolved through doi.org) in the
+/// `url_column` column of a CSV with a header row and import it like
+/// `copy_file_to_data`. At most `URL_IMPORT_CONCURRENCY` downloads run at once,
+/// each limited to `URL_IMPORT_TIMEOUT_SECS`. Results come back in row order;
+/// a failed row doesn't stop the others.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn import_url_list(
+    app: tauri::AppHandle,
+    csv_path: String,
+    url_column: String,
+) -> Result<Vec<UrlImportResult>, String> {
+    ensure_unlocked(&app)?;
+    let urls = read_csv_column(Path::new(&csv_path), &url_column)?;
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(URL_IMPORT_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let limit = std::sync::Arc::new(tokio::sync::Semaphore::new(URL_IMPORT_CONCURRENCY));
+
+    let tasks: Vec<_> = urls
+        .into_iter()
+        .enumerate()
+        .map(|(i, url)| {
+            let (app, client, limit) = (app.clone(), client.clone(), limit.clone());
+            tauri::async_runtime::spawn(async move {
+                let result = match limit.acquire_owned().await {
+                    Ok(_permit) => download_to_data(&app, &client, &url).await,
+                    Err(e) => Err(format!("Download queue closed: {}", e)),
+                };
+                let (filename, error) = match result {
+                    Ok(filename) => (Some(filename), None),
+                    Err(e) => (None, Some(e)),
+                };
+                UrlImportResult { row: i + 1, url, filename, error }
+            })
+        })
+        .collect();
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.map_err(|e| format!("Download task failed: {}", e))?);
+    }
+    Ok(results)
+}
+
+/// Values of the column named `column` (case-insensitive) in a CSV with a header row
+fn read_csv_column(path: &Path, column: &str) -> Result<Vec<String>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    let index = reader
+        .headers()
+        .map_err(|e| format!("Failed to read CSV header: {}", e))?
+        .iter()
+        .position(|header| header.trim().eq_ignore_ascii_case(column.trim()))
+        .ok_or_else(|| format!("No column named '{}'", column))?;
+    reader
+        .records()
+        .map(|record| {
+            record
+                .map(|r| r.get(index).unwrap_or("").trim().to_string())
+                .map_err(|e| format!("Failed to read CSV: {}", e))
+        })
+        .collect()
+}
+
+/// `https://doi.org/...` for a bare DOI (`10.1000/xyz` or `doi:10.1000/xyz`);
+/// http(s) URLs pass through and anything else is rejected
+fn download_url(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if let Some(doi) = normalize_doi(value).filter(|_| !value.starts_with("http")) {
+        let encoded: Vec<String> = doi.split('/').map(urlencoding_encode).collect();
+        return Ok(format!("https://doi.org/{}", encoded.join("/")));
+    }
+    if value.starts_with("http://") || value.starts_with("https://") {
+        return Ok(value.to_string());
+    }
+    Err(format!("Not a URL or DOI: '{}'", value))
+}
+
+/// Download a URL or DOI and import it through `copy_file_to_data`, so repeat
+/// downloads of the same file are recognized. Returns the data-dir filename.
+async fn download_to_data(app: &tauri::AppHandle, client: &reqwest::Client, value: &str) -> Result<String, String> {
+    let url = download_url(value)?;
+    let mut response = client
+        .get(&url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to download '{}': {}", url, e))?;
+    if response.content_length().is_some_and(|len| len > MAX_DOWNLOAD_BYTES as u64) {
+        return Err(format!("'{}' is larger than {} bytes", url, MAX_DOWNLOAD_BYTES));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let filename = download_filename(response.url().path(), &content_type);
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| format!("Failed to download '{}': {}", url, e))? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_DOWNLOAD_BYTES {
+            return Err(format!("'{}' is larger than {} bytes", url, MAX_DOWNLOAD_BYTES));
+        }
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        // Stage under the final name so the import picks it up
+        let staging = std::env::temp_dir().join(format!(
+            "peer-reviewer-download-{}-{}",
+            std::process::id(),
+            DOWNLOAD_STAGING_SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&staging).map_err(|e| format!("Failed to stage download: {}", e))?;
+        let staged = staging.join(&filename);
+        let result = fs::write(&staged, &body)
+            .map_err(|e| format!("Failed to stage download: {}", e))
+            .and_then(|_| copy_file_to_data(app, staged.to_string_lossy().to_string(), None));
+        let _ = fs::remove_dir_all(&staging);
+        result.map(|copied| copied.filename)
+    })
+    .await
+    .map_err(|e| format!("Import task failed: {}", e))?
+}
+
+/// Name for a download: the last URL path segment, with an extension from the
+/// `Content-Type` when it has none
+fn download_filename(url_path: &str, content_type: &str) -> String {
+    let segment = url_path.rsplit('/').find(|s| !s.is_empty()).unwrap_or("");
+    let mut name = sanitize_path_component(&urlencoding_decode(segment));
+    if segment.is_empty() {
+        name = "download".to_string();
+    }
+    // "1.2.3456"-style ids have dots but no extension
+    let has_extension = Path::new(&name)
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .is_some_and(|ext| ext.len() <= 5 && ext.chars().any(|c| c.is_ascii_alphabetic()));
+    if !has_extension {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        let ext = match mime {
+            "application/pdf" => Some("pdf"),
+            "text/html" | "application/xhtml+xml" => Some("html"),
+            "application/epub+zip" => Some("epub"),
+            "text/plain" => Some("txt"),
+            _ => None,
+        };
+        if let Some(ext) = ext {
+            name = format!("{}.{}", name, ext);
+        }
+    }
+    truncate_long_filename(&name, MAX_FILENAME_BYTES)
+}
+
+/// Crossref REST endpoint for a single work's metadata
+const CROSSREF_WORKS_URL: &str = "https://api.crossref.org/works/";
+const DOI_RESOLVE_TIMEOUT_SECS: u64 = 15;
+
+#[derive(serde::Serialize, Default, Debug)]
+struct DoiMetadata {
+    doi: String,
+    title: Option<String>,
+    /// "Given Family", in the order listed
+    authors: Vec<String>,
+    /// Journal, proceedings or book the work appeared in
+    container_title: Option<String>,
+    publisher: Option<String>,
+    year: Option<i32>,
+    volume: Option<String>,
+    issue: Option<String>,
+    pages: Option<String>,
+    /// Landing page the DOI resolves to
+    url: Option<String>,
+    /// Publisher-supplied PDF link, if any. It may still need a subscription
+    /// unless `open_access` is set.
+    pdf_url: Option<String>,
+    /// The work carries a Creative Commons license
+    open_access: bool,
+}
+
+/// Look up a DOI (bare, `doi:` or `https://doi.org/` form) on Crossref and
+/// return its bibliographic fields and PDF link
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn resolve_doi(app: tauri::AppHandle, doi: String) -> Result<DoiMetadata, String> {
+    ensure_unlocked(&app)?;
+    let doi = normalize_doi(&doi).ok_or_else(|| format!("Not a DOI: '{}'", doi))?;
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(DOI_RESOLVE_TIMEOUT_SECS))
+        .user_agent(concat!("PeerReviewer/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let encoded: Vec<String> = doi.split('/').map(urlencoding_encode).collect();
+    let response = client
+        .get(format!("{}{}", CROSSREF_WORKS_URL, encoded.join("/")))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach Crossref: {}", e))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(format!("DOI not found: {}", doi));
+    }
+    let bytes = response
+        .error_for_status()
+        .map_err(|e| format!("Crossref lookup failed: {}", e))?
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to read Crossref response: {}", e))?;
+    let body: serde_json::Value =
+        serde_json::from_slice(&bytes).map_err(|e| format!("Invalid Crossref response: {}", e))?;
+    Ok(crossref_metadata(&doi, &body["message"]))
+}
+
+/// The `10.xxxx/...` part of a DOI written bare, as `doi:...` or as a doi.org URL
+fn normalize_doi(value: &str) -> Option<String> {
+    let value = value.trim();
+    let lower = value.to_ascii_lowercase();
+    let prefix_len = ["https://doi.org/", "http://doi.org/", "https://dx.doi.org/", "http://dx.doi.org/", "doi:"]
+        .iter()
+        .find(|prefix| lower.starts_with(*prefix))
+        .map_or(0, |prefix| prefix.len());
+    let doi = value[prefix_len..].trim();
+    (doi.starts_with("10.") && doi.contains('/')).then(|| doi.to_string())
+}
+
+/// Bibliographic fields from a Crossref work record; missing fields stay empty
+fn crossref_metadata(doi: &str, work: &serde_json::Value) -> DoiMetadata {
+    // Crossref wraps most text fields in one-element arrays
+    let first = |key: &str| {
+        work[key]
+            .as_array()
+            .and_then(|values| values.first())
+            .or(Some(&work[key]))
+            .and_then(|v| v.as_str())
+            .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|s| !s.is_empty())
+    };
+    let authors = work["author"]
+        .as_array()
+        .map(|authors| {
+            authors
+                .iter()
+                .filter_map(|a| {
+                    let name = [a["given"].as_str(), a["family"].as_str().or(a["name"].as_str())]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    (!name.is_empty()).then_some(name)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let year = ["issued", "published-print", "published-online", "created"]
+        .iter()
+        .find_map(|key| work[*key]["date-parts"][0][0].as_i64())
+        .map(|y| y as i32);
+    let pdf_url = work["link"].as_array().and_then(|links| {
+        links
+            .iter()
+            .find(|link| link["content-type"].as_str() == Some("application/pdf"))
+            .and_then(|link| link["URL"].as_str())
+            .map(str::to_string)
+    });
+    let open_access = work["license"].as_array().is_some_and(|licenses| {
+        licenses
+            .iter()
+            .filter_map(|l| l["URL"].as_str())
+            .any(|url| url.contains("creativecommons.org"))
+    });
+    DoiMetadata {
+        doi: doi.to_string(),
+        title: first("title"),
+        authors,
+        container_title: first("container-title"),
+        publisher: first("publisher"),
+        year,
+        volume: first("volume"),
+        issue: first("issue"),
+        pages: first("page"),
+        url: first("URL"),
+        pdf_url,
+        open_access,
+    }
+}
+
+/// Type tag stored by `copy_file_to_data`, or `None` if the file was imported without one
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn read_file_type_tag(app: tauri::AppHandle, filename: String) -> Result<Option<String>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    resolve_data_path(&data_dir, &filename)?;
+    let path = meta_sidecar_path(&data_dir, &filename);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read metadata for '{}': {}", filename, e))?;
+    let meta: FileMeta = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid metadata for '{}': {}", filename, e))?;
+    Ok(Some(meta.file_type))
+}
+
+/// Suffix of the sidecar holding a file's tags as a JSON array
+const TAGS_SIDECAR_SUFFIX: &str = ".tags.json";
+
+fn tags_sidecar_path(data_dir: &Path, filename: &str) -> PathBuf {
+    data_dir.join(format!("{}{}", filename, TAGS_SIDECAR_SUFFIX))
+}
+
+/// Trimmed, non-empty and de-duplicated, keeping the caller's order
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
+fn read_file_tags(data_dir: &Path, filename: &str) -> Result<Vec<String>, String> {
+    let path = tags_sidecar_path(data_dir, filename);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read tags for '{}': {}", filename, e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("Invalid tags for '{}': {}", filename, e))
+}
+
+/// Replace a file's tags; an empty list removes the sidecar
+fn write_file_tags(data_dir: &Path, filename: &str, tags: Vec<String>) -> Result<(), String> {
+    let path = tags_sidecar_path(data_dir, filename);
+    let tags = normalize_tags(tags);
+    if tags.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove tags for '{}': {}", filename, e))?;
+        }
+        return Ok(());
+    }
+    let json = serde_json::to_string_pretty(&tags)
+        .map_err(|e| format!("Failed to serialize tags: {}", e))?;
+    write_within_quota(&path, None, json.as_bytes(), false, true, filename)
+}
+
+/// Tags stored in `<filename>.tags.json`; empty if the file has none
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_file_tags(app: tauri::AppHandle, filename: String) -> Result<Vec<String>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    resolve_data_path(&data_dir, &filename)?;
+    read_file_tags(&data_dir, &filename)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn set_file_tags(app: tauri::AppHandle, filename: String, tags: Vec<String>) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    ensure_writable(&app, &filename)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    if !resolve_data_path(&data_dir, &filename)?.is_file() {
+        return Err(format!("File does not exist: {}", filename));
+    }
+    write_file_tags(&data_dir, &filename, tags)
+}
+
+/// Files carrying `tag` (compared case-insensitively), sorted by path
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn find_files_by_tag(app: tauri::AppHandle, tag: String) -> Result<Vec<String>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    find_files_by_tag_in(&data_dir, tag.trim())
+}
+
+fn find_files_by_tag_in(data_dir: &Path, tag: &str) -> Result<Vec<String>, String> {
+    let tag = tag.to_lowercase();
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    let mut tagged = Vec::new();
+    for path in files {
+        let rel = relative_data_path(data_dir, &path)?;
+        let Some(filename) = rel.strip_suffix(TAGS_SIDECAR_SUFFIX) else {
+            continue;
+        };
+        // Sidecars can outlive their file if it was removed outside the app
+        if !data_dir.join(filename).is_file() {
+            continue;
+        }
+        match read_file_tags(data_dir, filename) {
+            Ok(tags) if tags.iter().any(|t| t.to_lowercase() == tag) => {
+                tagged.push(filename.to_string());
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("[find_files_by_tag] {}", e),
+        }
+    }
+    tagged.sort();
+    Ok(tagged)
+}
+
+/// `.json` files must be valid JSON and are returned as stored. `.json5` files
+/// (comments, trailing commas, unquoted keys...) are returned converted to JSON.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn read_data_file(app: tauri::AppHandle, filename: String) -> Result<String, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let content = read_data_file_in(&root, &relative)?;
+    normalize_json_content(&filename, content)
+}
+
+fn normalize_json_content(filename: &str, content: String) -> Result<String, String> {
+    let lower = filename.to_lowercase();
+    if lower.ends_with(".json5") {
+        let value: serde_json::Value = json5::from_str(&content)
+            .map_err(|e| format!("Invalid JSON5 in '{}': {}", filename, e))?;
+        serde_json::to_string(&value).map_err(|e| format!("Failed to serialize '{}': {}", filename, e))
+    } else if lower.ends_with(".json") {
+        serde_json::from_str::<serde::de::IgnoredAny>(&content)
+            .map_err(|e| format!("Invalid JSON in '{}': {}", filename, e))?;
+        Ok(content)
+    } else {
+        Ok(content)
+    }
+}
+
+/// Suffix `write_data_file` appends when storing content compressed
+const COMPRESSED_SUFFIX: &str = ".z";
+
+/// Where the data file `filename` is stored: the file itself, or `<filename>.z`
+/// when only the compressed form exists. The flag is set in the latter case.
+/// Paths escaping `data_dir` are refused.
+fn stored_data_path(data_dir: &Path, filename: &str) -> Result<(PathBuf, bool), String> {
+    let path = resolve_data_path(data_dir, filename)?;
+    let compressed_path = resolve_data_path(data_dir, &format!("{}{}", filename, COMPRESSED_SUFFIX))?;
+    if !path.exists() && compressed_path.exists() {
+        Ok((compressed_path, true))
+    } else {
+        Ok((path, false))
+    }
+}
+
+/// Reader over a stored data file's content, inflating a `.z` copy on the fly
+fn open_stored_data(path: &Path, compressed: bool) -> std::io::Result<Box<dyn std::io::Read + Send>> {
+    let file = fs::File::open(path)?;
+    Ok(if compressed { Box::new(flate2::read::ZlibDecoder::new(file)) } else { Box::new(file) })
+}
+
+/// Reads transparently decompress `<filename>.z` (zlib) and gzip `.gz` files
+fn read_data_file_in(data_dir: &Path, filename: &str) -> Result<String, String> {
+    use std::io::Read;
+
+    let (path, compressed) = stored_data_path(data_dir, filename)?;
+    let read_err = |e: std::io::Error| format!("Failed to read file '{}': {}", filename, e);
+    if compressed {
+        let mut content = String::new();
+        open_stored_data(&path, true)
+            .and_then(|mut reader| reader.read_to_string(&mut content))
+            .map_err(read_err)?;
+        return Ok(content);
+    }
+    let bytes = fs::read(&path).map_err(read_err)?;
+    if filename.ends_with(".gz") && bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(bytes.as_slice())
+            .read_to_string(&mut content)
+            .map_err(read_err)?;
+        return Ok(content);
+    }
+    String::from_utf8(bytes)
+        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))
+}
+
+/// Lines buffered between the file reader and the event emitter
+const LINE_STREAM_BUFFER: usize = 256;
+
+#[derive(Clone, serde::Serialize)]
+struct FileLineEvent {
+    index: usize,
+    line: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FileStreamCompleteEvent {
+    filename: String,
+    line_count: usize,
+}
+
+/// Stream a text file to the calling window one `file-line` event per line,
+/// then `file-stream-complete`, so long JSONL files render as they load. Lines
+/// are read on a separate task and handed over a bounded channel. Compressed
+/// files aren't decoded; use `read_data_file` for those.
+#[tauri::command]
+#[tracing::instrument(skip(app, window), err)]
+async fn stream_data_file_lines(
+    app: tauri::AppHandle,
+    window: tauri::Window,
+    filename: String,
+) -> Result<(), String> {
+    use std::io::Read;
+    use tauri::Emitter;
+    use tokio::io::AsyncBufReadExt;
+
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let (path, compressed) = stored_data_path(&root, &relative)?;
+    let open_err = |e: std::io::Error| format!("Failed to open file '{}': {}", filename, e);
+    // Compressed notes are small enough to inflate up front
+    let file: Box<dyn tokio::io::AsyncRead + Unpin + Send> = if compressed {
+        let mut content = Vec::new();
+        open_stored_data(&path, true)
+            .and_then(|mut reader| reader.read_to_end(&mut content))
+            .map_err(open_err)?;
+        Box::new(std::io::Cursor::new(content))
+    } else {
+        Box::new(tokio::fs::File::open(&path).await.map_err(open_err)?)
+    };
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(LINE_STREAM_BUFFER);
+    let reader_filename = filename.clone();
+    let reader = tauri::async_runtime::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(file).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    // The receiver only goes away once emitting has failed
+                    if sender.send(line).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(format!("Failed to read file '{}': {}", reader_filename, e)),
+            }
+        }
+    });
+
+    let mut line_count = 0;
+    while let Some(line) = receiver.recv().await {
+        window
+            .emit_to(window.label(), "file-line", FileLineEvent { index: line_count, line })
+            .map_err(|e| format!("Failed to emit file-line: {}", e))?;
+        line_count += 1;
+    }
+    reader.await.map_err(|e| format!("Line reader failed: {}", e))??;
+    window
+        .emit_to(window.label(), "file-stream-complete", FileStreamCompleteEvent { filename, line_count })
+        .map_err(|e| format!("Failed to emit file-stream-complete: {}", e))
+}
+
+/// Watchers behind `follow_data_file`, keyed by filename; dropping one stops it
+#[derive(Default)]
+struct FileFollowers(std::sync::Mutex<std::collections::HashMap<String, notify::RecommendedWatcher>>);
+
+#[derive(Clone, serde::Serialize)]
+struct FileAppendedEvent {
+    filename: String,
+    lines: Vec<String>,
+}
+
+/// Tauri only accepts alphanumerics and `-/:_` in event names
+fn follow_event_name(filename: &str) -> String {
+    let safe: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '/' | '_') { c } else { '_' })
+        .collect();
+    format!("file-appended:{}", safe)
+}
+
+/// Tail a growing file: every time complete lines are appended, emit them as
+/// `file-appended:<filename>` (characters Tauri doesn't allow in event names
+/// become `_`; the payload carries the real filename). Starts at the current
+/// end of the file and starts over from the top if the file is truncated.
+/// Following a file again replaces the previous watcher.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn follow_data_file(app: tauri::AppHandle, filename: String) -> Result<(), String> {
+    use notify::Watcher;
+    use tauri::Emitter;
+
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let path = resolve_data_path(&data_dir, &filename)?;
+    let mut offset = fs::metadata(&path)
+        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?
+        .len();
+    let parent = path.parent().map(Path::to_path_buf).unwrap_or_else(|| data_dir.clone());
+
+    let event_name = follow_event_name(&filename);
+    let (handler_app, handler_path, handler_filename) = (app.clone(), path.clone(), filename.clone());
+    let mut partial = Vec::new();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                log::warn!("[follow_data_file] Watch error for '{}': {}", handler_filename, e);
+                return;
+            }
+        };
+        if event.kind.is_access() || !event.paths.iter().any(|p| p == &handler_path) {
+            return;
+        }
+        match read_appended_lines(&handler_path, &mut offset, &mut partial) {
+            Ok(lines) if lines.is_empty() => {}
+            Ok(lines) => {
+                let payload = FileAppendedEvent { filename: handler_filename.clone(), lines };
+                if let Err(e) = handler_app.emit(&event_name, payload) {
+                    log::warn!("[follow_data_file] Failed to emit {}: {}", event_name, e);
+                }
+            }
+            // Deleted or mid-rename; the next create/modify event picks it up again
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("[follow_data_file] Failed to read '{}': {}", handler_filename, e),
+        }
+    })
+    .map_err(|e| format!("Failed to create file watcher: {}", e))?;
+    // Watch the directory rather than the file so a log that is recreated
+    // (e.g. rotated) keeps being followed
+    watcher
+        .watch(&parent, notify::RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Failed to watch '{}': {}", filename, e))?;
+
+    app.state::<FileFollowers>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(filename, watcher);
+    Ok(())
+}
+
+/// Stop a `follow_data_file` watcher. Returns whether the file was being followed.
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn stop_following(app: tauri::AppHandle, filename: String) -> bool {
+    app.state::<FileFollowers>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&filename)
+        .is_some()
+}
+
+/// Complete lines written past `offset`, advancing it. A trailing line without
+/// its newline yet is held in `partial` until the rest arrives.
+fn read_appended_lines(path: &Path, offset: &mut u64, partial: &mut Vec<u8>) -> std::io::Result<Vec<String>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    if len < *offset {
+        *offset = 0;
+        partial.clear();
+    }
+    file.seek(SeekFrom::Start(*offset))?;
+    let mut appended = Vec::new();
+    file.take(len - *offset).read_to_end(&mut appended)?;
+    *offset += appended.len() as u64;
+    partial.extend_from_slice(&appended);
+
+    let Some(last_newline) = partial.iter().rposition(|&b| b == b'\n') else {
+        return Ok(Vec::new());
+    };
+    let rest = partial.split_off(last_newline + 1);
+    let complete = std::mem::replace(partial, rest);
+    // `complete` ends with the newline, so drop the empty piece after it
+    Ok(complete[..complete.len() - 1]
+        .split(|&b| b == b'\n')
+        .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned())
+        .collect())
+}
+
+/// Read a page of lines. With `reverse`, the last line comes first: the file is
+/// read backwards from the end and `offset` counts from the end too.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn read_data_file_lines(
+    app: tauri::AppHandle,
+    filename: String,
+    offset: usize,
+    count: usize,
+    reverse: Option<bool>,
+) -> Result<Vec<String>, String> {
+    use std::io::BufRead;
+
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let (path, compressed) = stored_data_path(&root, &relative)?;
+    let result = if reverse.unwrap_or(false) && !compressed {
+        read_lines_reversed(&path, offset, count)
+    } else if reverse.unwrap_or(false) {
+        // A compressed stream can't be read from the end; inflate it all
+        open_stored_data(&path, true)
+            .and_then(|reader| std::io::BufReader::new(reader).lines().collect::<std::io::Result<Vec<_>>>())
+            .map(|lines| lines.into_iter().rev().skip(offset).take(count).collect())
+    } else {
+        let file = open_stored_data(&path, compressed)
+            .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+        std::io::BufReader::new(file)
+            .lines()
+            .skip(offset)
+            .take(count)
+            .collect()
+    };
+    result.map_err(|e| format!("Failed to read file '{}': {}", filename, e))
+}
+
+/// Lines from the end of a file backwards, scanning blocks for `\n` from the end
+fn read_lines_reversed(path: &Path, offset: usize, count: usize) -> std::io::Result<Vec<String>> {
+    use std::io::{Read, Seek, SeekFrom};
+    const BLOCK: u64 = 8192;
+
+    let mut file = fs::File::open(path)?;
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    let mut lines = Vec::new();
+    if pos == 0 || count == 0 {
+        return Ok(lines);
+    }
+
+    let mut skipped = 0;
+    let mut at_eof = true;
+    let mut emit = |raw: &[u8], lines: &mut Vec<String>| {
+        // A trailing newline doesn't start another (empty) line
+        if std::mem::take(&mut at_eof) && raw.is_empty() {
+            return;
+        }
+        if skipped < offset {
+            skipped += 1;
+        } else {
+            let line = String::from_utf8_lossy(raw);
+            lines.push(line.strip_suffix('\r').unwrap_or(&line).to_string());
+        }
+    };
+
+    // Bytes after the last newline seen so far, in file order
+    let mut tail: Vec<u8> = Vec::new();
+    while pos > 0 && lines.len() < count {
+        let read_len = BLOCK.min(pos);
+        pos -= read_len;
+        file.seek(SeekFrom::Start(pos))?;
+        let mut block = vec![0u8; read_len as usize];
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&tail);
+        tail = block;
+
+        while let Some(newline) = tail.iter().rposition(|&b| b == b'\n') {
+            emit(&tail[newline + 1..], &mut lines);
+            tail.truncate(newline);
+            if lines.len() == count {
+                return Ok(lines);
+            }
+        }
+    }
+    if pos == 0 && lines.len() < count {
+        emit(&tail, &mut lines);
+    }
+    Ok(lines)
+}
+
+/// Encoding used to hand binary file contents to the front-end
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+enum BinaryFormat {
+    #[default]
+    Base64,
+    Hex,
+    /// Only valid when the bytes are UTF-8; errors otherwise
+    RawUtf8,
+}
+
+/// Entries kept by the binary read cache
+const BINARY_CACHE_ENTRIES: usize = 64;
+/// Total bytes kept by the binary read cache
+const BINARY_CACHE_MAX_BYTES: usize = 128 * 1024 * 1024;
+
+/// Cached file bytes with the metadata they were read at, so a changed file is a miss
+struct CachedBinary {
+    modified: Option<std::time::SystemTime>,
+    bytes: Vec<u8>,
+}
+
+/// LRU cache for `read_data_file_binary(cache: true)`, bounded by entry count and total size
+struct BinaryCache {
+    state: std::sync::Mutex<BinaryCacheState>,
+    max_bytes: usize,
+}
+
+struct BinaryCacheState {
+    entries: lru::LruCache<String, CachedBinary>,
+    used_bytes: usize,
+}
+
+impl BinaryCacheState {
+    fn remove(&mut self, filename: &str) {
+        if let Some(old) = self.entries.pop(filename) {
+            self.used_bytes -= old.bytes.len();
+        }
+    }
+}
+
+impl BinaryCache {
+    fn new(max_entries: usize, max_bytes: usize) -> Self {
+        let capacity = std::num::NonZeroUsize::new(max_entries).unwrap_or(std::num::NonZeroUsize::MIN);
+        BinaryCache {
+            state: std::sync::Mutex::new(BinaryCacheState {
+                entries: lru::LruCache::new(capacity),
+                used_bytes: 0,
+            }),
+            max_bytes,
+        }
+    }
+
+    fn read(&self, filename: &str, path: &Path) -> Result<Vec<u8>, String> {
+        let metadata = fs::metadata(path)
+            .map_err(|e| format!("Failed to read binary file '{}': {}", filename, e))?;
+        let modified = metadata.modified().ok();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = state.entries.get(filename) {
+            if cached.modified == modified && cached.bytes.len() as u64 == metadata.len() {
+                return Ok(cached.bytes.clone());
+            }
+        }
+
+        let bytes = fs::read(path)
+            .map_err(|e| format!("Failed to read binary file '{}': {}", filename, e))?;
+        state.remove(filename);
+        if bytes.len() <= self.max_bytes {
+            while state.used_bytes + bytes.len() > self.max_bytes {
+                let Some((_, evicted)) = state.entries.pop_lru() else { break };
+                state.used_bytes -= evicted.bytes.len();
+            }
+            state.used_bytes += bytes.len();
+            let cached = CachedBinary { modified, bytes: bytes.clone() };
+            if let Some((_, evicted)) = state.entries.push(filename.to_string(), cached) {
+                state.used_bytes -= evicted.bytes.len();
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn evict(&self, filename: &str) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).remove(filename);
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.entries.clear();
+        state.used_bytes = 0;
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn clear_binary_cache(app: tauri::AppHandle) {
+    app.state::<BinaryCache>().clear();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn evict_binary_cache_entry(app: tauri::AppHandle, filename: String) {
+    app.state::<BinaryCache>().evict(&filename);
+}
+
+/// Read a binary file. When `expected_size_bytes` is given, a size mismatch (e.g. a
+/// file truncated by a crash mid-write) is an error reported before anything is read.
+/// Likewise a file larger than `max_size_bytes` is rejected before a buffer is
+/// allocated for it. With `cache`, repeat reads of an unchanged file are served
+/// from memory. With `resize_to` (width, height), an image is scaled down to fit
+/// that box and returned as JPEG instead (needs the `image-processing` feature).
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn read_data_file_binary(
+    app: tauri::AppHandle,
+    filename: String,
+    format: Option<BinaryFormat>,
+    expected_size_bytes: Option<u64>,
+    cache: Option<bool>,
+    max_size_bytes: Option<u64>,
+    resize_to: Option<(u32, u32)>,
+) -> Result<String, String> {
+    use std::io::Read;
+
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let (path, compressed) = stored_data_path(&root, &relative)?;
+    let read_err = |e: std::io::Error| format!("Failed to read binary file '{}': {}", filename, e);
+    // The size on disk of a compressed file says nothing about its content
+    let inflated = if compressed {
+        let mut content = Vec::new();
+        open_stored_data(&path, true)
+            .and_then(|mut reader| reader.read_to_end(&mut content))
+            .map_err(read_err)?;
+        Some(content)
+    } else {
+        None
+    };
+    if expected_size_bytes.is_some() || max_size_bytes.is_some() {
+        let actual = match &inflated {
+            Some(content) => content.len() as u64,
+            None => fs::metadata(&path).map_err(read_err)?.len(),
+        };
+        if let Some(expected) = expected_size_bytes.filter(|&expected| expected != actual) {
+            return Err(format!(
+                "Size mismatch for '{}': expected {} bytes, found {}",
+                filename, expected, actual
+            ));
+        }
+        if let Some(max) = max_size_bytes.filter(|&max| actual > max) {
+            return Err(format!(
+                "File '{}' is {} bytes, over the {} byte limit",
+                filename, actual, max
+            ));
+        }
+    }
+    let bytes = match inflated {
+        Some(content) => content,
+        None if cache.unwrap_or(false) => app.state::<BinaryCache>().read(&filename, &path)?,
+        None => fs::read(&path).map_err(read_err)?,
+    };
+    let bytes = match resize_to {
+        Some((width, height)) => resize_image_to_jpeg(&bytes, width, height)
+            .map_err(|e| format!("Failed to resize '{}': {}", filename, e))?,
+        None => bytes,
+    };
+    match format.unwrap_or_default() {
+        BinaryFormat::Base64 => Ok(general_purpose::STANDARD.encode(&bytes)),
+        BinaryFormat::Hex => Ok(hex_encode(&bytes)),
+        BinaryFormat::RawUtf8 => String::from_utf8(bytes)
+            .map_err(|e| format!("File '{}' is not valid UTF-8: {}", filename, e)),
+    }
+}
+
+/// Most bytes `hexdump_data_file` formats in one call
+const HEXDUMP_MAX_BYTES: u64 = 1024 * 1024;
+
+/// `hexdump -C` style dump of `length` bytes from `offset`: 16 bytes per line
+/// with the file offset and a printable-ASCII column. The range is clamped to
+/// the file (an offset past the end gives an empty dump) and to `HEXDUMP_MAX_BYTES`.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn hexdump_data_file(app: tauri::AppHandle, filename: String, offset: u64, length: u64) -> Result<String, String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let read_err = |e: std::io::Error| format!("Failed to read file '{}': {}", filename, e);
+    let (path, compressed) = stored_data_path(&root, &relative)?;
+    if compressed {
+        // No seeking in a compressed stream; skip up to `offset` instead
+        let mut reader = open_stored_data(&path, true).map_err(read_err)?;
+        let start = std::io::copy(&mut reader.by_ref().take(offset), &mut std::io::sink()).map_err(read_err)?;
+        let mut bytes = Vec::new();
+        reader.take(length.min(HEXDUMP_MAX_BYTES)).read_to_end(&mut bytes).map_err(read_err)?;
+        return Ok(hexdump(&bytes, start));
+    }
+    let mut file = fs::File::open(&path).map_err(read_err)?;
+    let size = file.metadata().map_err(read_err)?.len();
+    let offset = offset.min(size);
+    let length = length.min(size - offset).min(HEXDUMP_MAX_BYTES);
+    file.seek(SeekFrom::Start(offset)).map_err(read_err)?;
+    let mut bytes = Vec::with_capacity(length as usize);
+    file.take(length).read_to_end(&mut bytes).map_err(read_err)?;
+    Ok(hexdump(&bytes, offset))
+}
+
+fn hexdump(bytes: &[u8], start: u64) -> String {
+    let mut out = String::with_capacity(bytes.len() / 16 * 80 + 80);
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        out.push_str(&format!("{:08x} ", start + i as u64 * 16));
+        for column in 0..16 {
+            if column == 8 {
+                out.push(' ');
+            }
+            match chunk.get(column) {
+                Some(b) => out.push_str(&format!(" {:02x}", b)),
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+/// Decode an image, scale it (Lanczos) to fit within `width` x `height` keeping
+/// its aspect ratio, and encode it as JPEG. Images already inside the box are
+/// re-encoded at their own size.
+#[cfg(feature = "image-processing")]
+fn resize_image_to_jpeg(bytes: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid size {}x{}", width, height));
+    }
+    let image = image::load_from_memory(bytes).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let image = if image.width() > width || image.height() > height {
+        image.resize(width, height, image::imageops::FilterType::Lanczos3)
+    } else {
+        image
+    };
+    let mut jpeg = std::io::Cursor::new(Vec::new());
+    // JPEG has no alpha channel
+    image::DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    Ok(jpeg.into_inner())
+}
+
+#[cfg(not(feature = "image-processing"))]
+fn resize_image_to_jpeg(_bytes: &[u8], _width: u32, _height: u32) -> Result<Vec<u8>, String> {
+    Err("image resizing is disabled in this build".to_string())
+}
+
+/// Lowercase hex encoding of a byte slice
+fn hex_encode(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        out.push(DIGITS[(b >> 4) as usize] as char);
+        out.push(DIGITS[(b & 0x0f) as usize] as char);
+    }
+    out
+}
+
+/// Largest file `read_data_file_with_hash` will load into memory
+const MAX_HASHED_READ_BYTES: u64 = 100 * 1024 * 1024;
+
+#[derive(serde::Serialize)]
+struct HashedContent {
+    /// Base64 file content
+    content: String,
+    /// Hex SHA-256 of the same bytes
+    sha256: String,
+}
+
+/// Read a file once and return its content with the SHA-256 of exactly those
+/// bytes, for callers that key caches on content
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn read_data_file_with_hash(app: tauri::AppHandle, filename: String) -> Result<HashedContent, String> {
+    use sha2::Digest;
+    use std::io::Read;
+
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let (path, compressed) = stored_data_path(&root, &relative)?;
+    let file = open_stored_data(&path, compressed)
+        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    // Read through a limit rather than trusting metadata, which can change under us
+    let mut bytes = Vec::new();
+    file.take(MAX_HASHED_READ_BYTES + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    if bytes.len() as u64 > MAX_HASHED_READ_BYTES {
+        return Err(format!(
+            "File '{}' exceeds the {} byte limit",
+            filename, MAX_HASHED_READ_BYTES
+        ));
+    }
+    Ok(HashedContent {
+        sha256: hex_encode(&sha2::Sha256::digest(&bytes)),
+        content: general_purpose::STANDARD.encode(&bytes),
+    })
+}
+
+/// SHA-256 of a text note with every whitespace run collapsed to one space and
+/// the ends trimmed, so copies differing only in formatting hash the same
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn normalized_content_hash(app: tauri::AppHandle, filename: String) -> Result<String, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    normalized_content_hash_in(&root, &relative)
+}
+
+fn normalized_content_hash_in(data_dir: &Path, filename: &str) -> Result<String, String> {
+    use sha2::Digest;
+
+    if !is_text_file(Path::new(filename)) {
+        return Err(format!("'{}' is not a text file", filename));
+    }
+    let content = read_data_file_in(data_dir, filename)?;
+    let mut hasher = sha2::Sha256::new();
+    for (i, word) in content.split_whitespace().enumerate() {
+        if i > 0 {
+            hasher.update(b" ");
+        }
+        hasher.update(word.as_bytes());
+    }
+    Ok(hex_encode(&hasher.finalize()))
+}
+
+/// Groups of text notes with the same `normalized_content_hash`, each sorted by
+/// path; notes without a near-duplicate are left out. Binary files are skipped.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn find_near_duplicate_notes(app: tauri::AppHandle) -> Result<Vec<Vec<String>>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || find_near_duplicate_notes_in(&data_dir)).await
+}
+
+fn find_near_duplicate_notes_in(data_dir: &Path) -> Result<Vec<Vec<String>>, String> {
+    use rayon::prelude::*;
+
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    let hashed: Vec<(String, String)> = files
+        .par_iter()
+        .filter(|path| is_text_file(path))
+        .filter_map(|path| {
+            let rel = relative_data_path(data_dir, path).ok()?;
+            match normalized_content_hash_in(data_dir, &rel) {
+                Ok(hash) => Some((hash, rel)),
+                Err(e) => {
+                    log::warn!("[find_near_duplicate_notes] Skipping '{}': {}", rel, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let mut groups: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
+    for (hash, rel) in hashed {
+        groups.entry(hash).or_default().push(rel);
+    }
+    let mut duplicates: Vec<Vec<String>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    for group in &mut duplicates {
+        group.sort();
+    }
+    duplicates.sort();
+    Ok(duplicates)
+}
+
+/// Side of the downscaled grayscale image a perceptual hash is computed from
+const PHASH_SAMPLE_SIZE: usize = 32;
+/// Side of the low-frequency DCT block kept, giving a 64-bit hash
+const PHASH_BLOCK_SIZE: usize = 8;
+
+/// Groups of images whose perceptual hashes (pHash) differ in at most
+/// `threshold` of 64 bits, so re-saved or recompressed copies of a figure are
+/// found even though their bytes differ. Around 10 catches recompression while
+/// keeping distinct figures apart. Groups are linked transitively, each sorted
+/// by path; thumbnails and images that fail to decode are skipped.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn find_similar_images(app: tauri::AppHandle, threshold: u32) -> Result<Vec<Vec<String>>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || find_similar_images_in(&data_dir, threshold)).await
+}
+
+fn find_similar_images_in(data_dir: &Path, threshold: u32) -> Result<Vec<Vec<String>>, String> {
+    use rayon::prelude::*;
+
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    let hashed: Vec<(String, u64)> = files
+        .par_iter()
+        .filter(|path| {
+            let is_image = path
+                .extension()
+                .is_some_and(|ext| THUMBNAIL_IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()));
+            let is_thumb = path.file_stem().is_some_and(|stem| stem.to_string_lossy().ends_with("_thumb"));
+            is_image && !is_thumb
+        })
+        .filter_map(|path| {
+            let rel = relative_data_path(data_dir, path).ok()?;
+            match image::open(path) {
+                Ok(img) => Some((rel, perceptual_hash(&img))),
+                Err(e) => {
+                    log::warn!("[find_similar_images] Skipping '{}': {}", rel, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // Union-find over every pair within the threshold
+    let mut parent: Vec<usize> = (0..hashed.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if (hashed[i].1 ^ hashed[j].1).count_ones() <= threshold {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+    let mut groups: std::collections::HashMap<usize, Vec<String>> = std::collections::HashMap::new();
+    for (i, (rel, _)) in hashed.iter().enumerate() {
+        groups.entry(root(&mut parent, i)).or_default().push(rel.clone());
+    }
+    let mut similar: Vec<Vec<String>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    for group in &mut similar {
+        group.sort();
+    }
+    similar.sort();
+    Ok(similar)
+}
+
+/// 64-bit pHash: the sign pattern of the lowest 8x8 DCT frequencies of a 32x32
+/// grayscale thumbnail against their median (the DC term left out of the median)
+fn perceptual_hash(img: &image::DynamicImage) -> u64 {
+    const N: usize = PHASH_SAMPLE_SIZE;
+    const K: usize = PHASH_BLOCK_SIZE;
+
+    let gray = img
+        .resize_exact(N as u32, N as u32, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = gray.pixels().map(|p| p.0[0] as f64).collect();
+    // cos_table[u][x] = cos((2x + 1) u pi / 2N), shared by both passes
+    let cos_table: Vec<Vec<f64>> = (0..K)
+        .map(|u| {
+            (0..N)
+                .map(|x| ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * N) as f64).cos())
+                .collect()
+        })
+        .collect();
+    // Separable DCT-II, only for the K lowest frequencies: rows first, then columns
+    let rows: Vec<[f64; K]> = (0..N)
+        .map(|y| {
+            let mut out = [0.0; K];
+            for (u, coeff) in out.iter_mut().enumerate() {
+                *coeff = (0..N).map(|x| pixels[y * N + x] * cos_table[u][x]).sum();
+            }
+            out
+        })
+        .collect();
+    let mut coeffs = [0.0f64; K * K];
+    for v in 0..K {
+        for u in 0..K {
+            coeffs[v * K + u] = (0..N).map(|y| rows[y][u] * cos_table[v][y]).sum();
+        }
+    }
+
+    let mut ac: Vec<f64> = coeffs[1..].to_vec();
+    ac.sort_by(|a, b| a.total_cmp(b));
+    let median = (ac[ac.len() / 2 - 1] + ac[ac.len() / 2]) / 2.0;
+    coeffs
+        .iter()
+        .enumerate()
+        .fold(0u64, |hash, (i, &c)| if c > median { hash | (1 << i) } else { hash })
+}
+
+/// Longest passage two texts share. Offsets and `length` are UTF-16 code units
+/// so they index JavaScript strings directly.
+#[derive(serde::Serialize)]
+struct CommonPassage {
+    passage: String,
+    a_offset: usize,
+    b_offset: usize,
+    length: usize,
+}
+
+/// The longest contiguous passage `file_a` and `file_b` have in common, or
+/// `None` if it is shorter than `min_length` characters. Linear time in the
+/// combined length (suffix automaton over `file_a`).
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn longest_common_passage(
+    app: tauri::AppHandle,
+    file_a: String,
+    file_b: String,
+    min_length: usize,
+) -> Result<Option<CommonPassage>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let (root_a, relative_a) = read_root(&app, data_dir.clone(), &file_a)?;
+    let (root_b, relative_b) = read_root(&app, data_dir, &file_b)?;
+    run_limited(&app, move || {
+        let a = read_data_file_in(&root_a, &relative_a)?;
+        let b = read_data_file_in(&root_b, &relative_b)?;
+        Ok(longest_common_passage_in(&a, &b, min_length))
+    })
+    .await
+}
+
+fn longest_common_passage_in(a: &str, b: &str, min_length: usize) -> Option<CommonPassage> {
+    struct State {
+        len: usize,
+        link: Option<usize>,
+        next: std::collections::HashMap<char, usize>,
+        /// End (char index in `a`) of the first occurrence of this state's strings
+        first_end: usize,
+    }
+
+    let a_chars: Vec<char> = a.chars().collect();
+    let mut states = vec![State { len: 0, link: None, next: Default::default(), first_end: 0 }];
+    let mut last = 0;
+    for (i, &c) in a_chars.iter().enumerate() {
+        let cur = states.len();
+        states.push(State { len: states[last].len + 1, link: Some(0), next: Default::default(), first_end: i });
+        let mut p = Some(last);
+        while let Some(state) = p.filter(|&s| !states[s].next.contains_key(&c)) {
+            states[state].next.insert(c, cur);
+            p = states[state].link;
+        }
+        if let Some(p) = p {
+            let q = states[p].next[&c];
+            if states[p].len + 1 == states[q].len {
+                states[cur].link = Some(q);
+            } else {
+                let clone = states.len();
+                states.push(State {
+                    len: states[p].len + 1,
+                    link: states[q].link,
+                    next: states[q].next.clone(),
+                    first_end: states[q].first_end,
+                });
+                let mut p = Some(p);
+                while let Some(state) = p.filter(|&s| states[s].next.get(&c) == Some(&q)) {
+                    states[state].next.insert(c, clone);
+                    p = states[state].link;
+                }
+                states[q].link = Some(clone);
+                states[cur].link = Some(clone);
+            }
+        }
+        last = cur;
+    }
+
+    // Walk `b` through the automaton, tracking the longest match ending at each char
+    let b_chars: Vec<char> = b.chars().collect();
+    let (mut state, mut len) = (0, 0);
+    // (length, end in a, end in b), ends inclusive
+    let mut best = (0, 0, 0);
+    for (i, c) in b_chars.iter().enumerate() {
+        while state != 0 && !states[state].next.contains_key(c) {
+            state = states[state].link.unwrap_or(0);
+            len = states[state].len;
+        }
+        match states[state].next.get(c) {
+            Some(&next) => {
+                state = next;
+                len += 1;
+            }
+            None => continue,
+        }
+        if len > best.0 {
+            best = (len, states[state].first_end, i);
+        }
+    }
+
+    let (len, a_end, b_end) = best;
+    if len == 0 || len < min_length {
+        return None;
+    }
+    let utf16_len = |chars: &[char]| chars.iter().map(|c| c.len_utf16()).sum::<usize>();
+    let a_start = a_end + 1 - len;
+    let passage: String = a_chars[a_start..=a_end].iter().collect();
+    Some(CommonPassage {
+        a_offset: utf16_len(&a_chars[..a_start]),
+        b_offset: utf16_len(&b_chars[..b_end + 1 - len]),
+        length: passage.encode_utf16().count(),
+        passage,
+    })
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FileWrittenEvent {
+    filename: String,
+    size_bytes: u64,
+    timestamp: u64,
+}
+
+/// Write a text file and emit `file-written` so other views can refresh their caches.
+/// The event carries the file as stored, e.g. `note.md.z` and its compressed
+/// size when `compress` is set. `suppress_event` skips the event for the file
+/// itself, e.g. for a view saving its own state.
+/// Fails if the write would exceed the parent directory's `.quota.json`.
+/// With `durable`, the content is flushed to disk (fsync) before this returns,
+/// at the cost of waiting for the device; see `atomic_write_durable`. With
+/// `tags`, the file's tags are replaced (see `set_file_tags`). Returns the
+/// filename actually written, after `sanitize_filename`.
+///
+/// A filename of `pipe:<path>` hands `content` to an external tool instead:
+/// `<path>` is a FIFO (`mkfifo`) on Unix or a named pipe (`\\.\pipe\<name>`)
+/// on Windows. The tool must already be reading or listening, otherwise this
+/// fails rather than waiting. Nothing touches the data dir, no event is
+/// emitted, the other options are ignored, and the filename is returned unchanged.
+#[tauri::command]
+#[tracing::instrument(skip(app, content), err)]
+fn write_data_file(
+    app: tauri::AppHandle,
+    filename: String,
+    content: String,
+    suppress_event: Option<bool>,
+    compress: Option<bool>,
+    durable: Option<bool>,
+    tags: Option<Vec<String>>,
+) -> Result<String, String> {
+    ensure_unlocked(&app)?;
+    if let Some(pipe) = filename.strip_prefix(PIPE_PREFIX) {
+        write_to_pipe(Path::new(pipe), content.as_bytes())?;
+        return Ok(filename);
+    }
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let filename = sanitize_write_path(&data_dir, &filename)?;
+    ensure_writable(&app, &filename)?;
+    let (compress, durable) = (compress.unwrap_or(false), durable.unwrap_or(false));
+    let notify = !suppress_event.unwrap_or(false);
+    write_data_file_in(&data_dir, &filename, &content, compress, durable, notify)?;
+    if let Some(tags) = tags {
+        write_file_tags(&data_dir, &filename, tags)?;
+    }
+    Ok(filename)
+}
+
+/// Emit `file-written` for a file stored at `path`, named relative to the data dir
+fn emit_file_written(app: &tauri::AppHandle, path: &Path, size_bytes: u64) {
+    use tauri::Emitter;
+
+    let Ok(filename) = get_data_dir(app).and_then(|data_dir| relative_data_path(&data_dir, path)) else {
+        return;
+    };
+    if filename.split('/').any(is_helper_entry) {
+        return;
+    }
+    let event = FileWrittenEvent { filename, size_bytes, timestamp: now_millis() };
+    if let Err(e) = app.emit("file-written", event) {
+        log::warn!("[write] Failed to emit file-written: {}", e);
+    }
+}
+
+/// `write_data_file` filenames with this prefix name a named pipe, not a data file
+const PIPE_PREFIX: &str = "pipe:";
+
+/// Send `content` to a reader that is already waiting on a FIFO
+#[cfg(unix)]
+fn write_to_pipe(path: &Path, content: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+    use std::os::unix::io::AsRawFd;
+
+    let file_type = fs::metadata(path)
+        .map_err(|e| format!("Failed to open pipe '{}': {}", path.display(), e))?
+        .file_type();
+    if !file_type.is_fifo() {
+        return Err(format!("Not a named pipe: {}", path.display()));
+    }
+    // A blocking open waits for a reader indefinitely; non-blocking fails with ENXIO instead
+    let mut pipe = fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::ENXIO) => format!("No process is reading from pipe: {}", path.display()),
+            _ => format!("Failed to open pipe '{}': {}", path.display(), e),
+        })?;
+    // Back to blocking, so content larger than the pipe buffer waits for the reader
+    // SAFETY: the descriptor belongs to `pipe`, which outlives both calls
+    let blocking = unsafe {
+        let fd = pipe.as_raw_fd();
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) >= 0
+    };
+    if !blocking {
+        return Err(format!(
+            "Failed to configure pipe '{}': {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    pipe.write_all(content)
+        .map_err(|e| format!("Failed to write to pipe '{}': {}", path.display(), e))
+}
+
+/// Send `content` to a named pipe server (`\\.\pipe\<name>`) that is already listening
+#[cfg(windows)]
+fn write_to_pipe(path: &Path, content: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+    if !path.to_string_lossy().to_ascii_lowercase().starts_with(r"\\.\pipe\") {
+        return Err(format!("Not a named pipe (expected \\\\.\\pipe\\<name>): {}", path.display()));
+    }
+    let mut pipe = fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(ERROR_PIPE_BUSY) => format!("Pipe is busy with another client: {}", path.display()),
+            _ if e.kind() == std::io::ErrorKind::NotFound => {
+                format!("No process is listening on pipe: {}", path.display())
+            }
+            _ => format!("Failed to open pipe '{}': {}", path.display(), e),
+        })?;
+    pipe.write_all(content)
+        .map_err(|e| format!("Failed to write to pipe '{}': {}", path.display(), e))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write_to_pipe(path: &Path, _content: &[u8]) -> Result<(), String> {
+    Err(format!("Writing to pipes isn't supported on this platform: {}", path.display()))
+}
+
+/// Serializes the check-and-write in `write_data_file_versioned`
+#[derive(Default)]
+struct VersionedWrites(std::sync::Mutex<()>);
+
+/// Version of a file's content for `write_data_file_versioned`: the first bytes
+/// of its SHA-256, cut to 53 bits so it survives as a JavaScript number. A
+/// missing file is version 0.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_data_file_version(app: tauri::AppHandle, filename: String) -> Result<u64, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    data_file_version_in(&root, &relative)
+}
+
+fn data_file_version_in(data_dir: &Path, filename: &str) -> Result<u64, String> {
+    use sha2::Digest;
+
+    let path = resolve_data_path(data_dir, filename)?;
+    let compressed_path = data_dir.join(format!("{}{}", filename, COMPRESSED_SUFFIX));
+    if !path.exists() && !compressed_path.exists() {
+        return Ok(0);
+    }
+    let digest = sha2::Sha256::digest(read_data_file_in(data_dir, filename)?.as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    Ok(u64::from_be_bytes(prefix) & ((1 << 53) - 1))
+}
+
+/// Write a file only if it is still at `expected_version` (from
+/// `get_data_file_version` or the previous versioned write; 0 to create it), so
+/// a stale editor can't overwrite someone else's changes. Returns the new version.
+/// The filename is sanitized as in `write_data_file`.
+#[tauri::command]
+#[tracing::instrument(skip(app, content), err)]
+fn write_data_file_versioned(
+    app: tauri::AppHandle,
+    filename: String,
+    content: String,
+    expected_version: u64,
+) -> Result<u64, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let filename = sanitize_write_path(&data_dir, &filename)?;
+    ensure_writable(&app, &filename)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let version = {
+        let writes = app.state::<VersionedWrites>();
+        let _guard = writes.0.lock().unwrap_or_else(|e| e.into_inner());
+        write_data_file_versioned_in(&data_dir, &filename, &content, expected_version)?
+    };
+    Ok(version)
+}
+
+fn write_data_file_versioned_in(
+    data_dir: &Path,
+    filename: &str,
+    content: &str,
+    expected_version: u64,
+) -> Result<u64, String> {
+    if data_file_version_in(data_dir, filename)? != expected_version {
+        return Err("conflict: file was modified externally".to_string());
+    }
+    write_data_file_in(data_dir, filename, content, false, false, true)?;
+    data_file_version_in(data_dir, filename)
+}
+
+/// Write a note after tidying it (see `tidy_note`) and return the tidied length in
+/// bytes. The filename is sanitized as in `write_data_file`.
+#[tauri::command]
+#[tracing::instrument(skip(app, content), err)]
+fn write_data_file_tidy(app: tauri::AppHandle, filename: String, content: String) -> Result<usize, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let filename = sanitize_write_path(&data_dir, &filename)?;
+    ensure_writable(&app, &filename)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let tidied = tidy_note(&content);
+    write_data_file_in(&data_dir, &filename, &tidied, false, false, true)?;
+    Ok(tidied.len())
+}
+
+/// Trim trailing whitespace and collapse runs of 3+ blank lines into one.
+/// Fenced code blocks are left untouched, a Markdown hard break (two trailing
+/// spaces after text) survives as exactly two spaces, and CRLF files stay CRLF.
+fn tidy_note(content: &str) -> String {
+    let newline = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    let lines: Vec<&str> = content.split('\n').collect();
+    let last = lines.len() - 1;
+    let mut out: Vec<String> = Vec::with_capacity(lines.len());
+    // Marker (``` or ~~~ run) of the fenced block we are inside, if any
+    let mut fence: Option<&str> = None;
+    let mut blank_run = 0;
+    for (i, raw) in lines.iter().enumerate() {
+        let line = raw.strip_suffix('\r').unwrap_or(raw);
+        if let Some(open) = fence {
+            out.push(line.to_string());
+            let closes = fence_marker(line)
+                .is_some_and(|m| m.starts_with(open) && line.trim_start()[m.len()..].trim().is_empty());
+            if closes {
+                fence = None;
+            }
+            continue;
+        }
+        let trimmed = line.trim_end();
+        // The piece after a final newline is the end of the file, not a blank line
+        if trimmed.is_empty() && i < last {
+            blank_run += 1;
+            continue;
+        }
+        let blanks = if blank_run >= 3 { 1 } else { blank_run };
+        out.extend((0..blanks).map(|_| String::new()));
+        blank_run = 0;
+
+        fence = fence_marker(line);
+        let hard_break = !trimmed.is_empty() && fence.is_none() && line[trimmed.len()..].starts_with("  ");
+        out.push(if hard_break { format!("{}  ", trimmed) } else { trimmed.to_string() });
+    }
+    out.join(newline)
+}
+
+/// The ``` or ~~~ run opening/closing a fenced code block (up to 3 spaces of indent)
+fn fence_marker(line: &str) -> Option<&str> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let ch = rest.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let run = rest.len() - rest.trim_start_matches(ch).len();
+    (run >= 3).then(|| &rest[..run])
+}
+
+/// With `compress`, `.gz` names get gzip in place and anything else is stored as
+/// zlib in `<filename>.z`. Only one of the plain and `.z` forms is kept, so reads
+/// never see a stale copy. `notify` is passed on to `write_within_quota`.
+fn write_data_file_in(
+    data_dir: &Path,
+    filename: &str,
+    content: &str,
+    compress: bool,
+    durable: bool,
+    notify: bool,
+) -> Result<(), String> {
+    use std::io::Write;
+
+    let path = data_dir.join(filename);
+    let compressed_path = data_dir.join(format!("{}{}", filename, COMPRESSED_SUFFIX));
+    let write_err = |e: std::io::Error| format!("Failed to write file '{}': {}", filename, e);
+    let (target, stale, bytes) = if !compress {
+        (path, compressed_path, content.as_bytes().to_vec())
+    } else if filename.ends_with(".gz") {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).map_err(write_err)?;
+        (path, compressed_path, encoder.finish().map_err(write_err)?)
+    } else {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).map_err(write_err)?;
+        (compressed_path, path, encoder.finish().map_err(write_err)?)
+    };
+    write_within_quota(&target, Some(&stale), &bytes, durable, notify, filename)
+}
+
+/// Per-directory size cap, read from `<dir>/.quota.json`
+const QUOTA_FILE: &str = ".quota.json";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DirectoryQuota {
+    max_bytes: u64,
+}
+
+/// The running app, so the shared write path can reach `ListCache` and emit
+/// events. Set once in
+/// `setup`; unit tests run without it.
+static APP_HANDLE: std::sync::OnceLock<tauri::AppHandle> = std::sync::OnceLock::new();
+
+/// Atomically write `bytes` to `target` in the data dir, unless that would exceed
+/// its directory's quota (see `check_directory_quota`). `replaced`, another
+/// stored form of the same file, is removed afterwards. Every write of a data
+/// file, sidecar or thumbnail goes through here; only the app's bookkeeping (the
+/// index, quota, passphrase and log files) is written directly. Missing
+/// subdirectories are created, and the listing cache is cleared once the write
+/// is done. With `notify`, `file-written` is emitted for the stored file unless
+/// it sits in a helper directory such as `.blobs`.
+fn write_within_quota(
+    target: &Path,
+    replaced: Option<&Path>,
+    bytes: &[u8],
+    durable: bool,
+    notify: bool,
+    filename: &str,
+) -> Result<(), String> {
+    let write_err = |e: std::io::Error| format!("Failed to write file '{}': {}", filename, e);
+    check_directory_quota(target, replaced, bytes.len() as u64)?;
+    let written = if durable { atomic_write_durable(target, bytes) } else { atomic_write(target, bytes) };
+    written.map_err(write_err)?;
+    if let Some(stale) = replaced.filter(|stale| stale.exists()) {
+        fs::remove_file(stale).map_err(write_err)?;
+    }
+    if let Some(app) = APP_HANDLE.get() {
+        app.state::<ListCache>().invalidate();
+        if notify {
+            emit_file_written(app, target, bytes.len() as u64);
+        }
+    }
+    Ok(())
+}
+
+/// `write_within_quota` with the content of `source`, for imports
+fn copy_within_quota(source: &Path, target: &Path, filename: &str) -> Result<(), String> {
+    let bytes = fs::read(source).map_err(|e| format!("Failed to read '{}': {}", source.display(), e))?;
+    write_within_quota(target, None, &bytes, false, true, filename)
+}
+
+/// Fail if writing `new_len` bytes to `target` (and removing `replaced`, the other
+/// stored form of the same file) would push its directory over the quota in the
+/// directory's `.quota.json`. Directories without one are unlimited. Internal
+/// files (the quota file itself, dot-files, sidecars) don't count.
+fn check_directory_quota(target: &Path, replaced: Option<&Path>, new_len: u64) -> Result<(), String> {
+    let Some(dir) = target.parent() else {
+        return Ok(());
+    };
+    let quota_path = dir.join(QUOTA_FILE);
+    if !quota_path.is_file() {
+        return Ok(());
+    }
+    let quota: DirectoryQuota = fs::read_to_string(&quota_path)
+        .map_err(|e| e.to_string())
+        .and_then(|raw| serde_json::from_str(&raw).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Invalid quota file '{}': {}", quota_path.display(), e))?;
+
+    let mut files = Vec::new();
+    collect_files(dir, true, &mut files)?;
+    let current: u64 = files
+        .iter()
+        .filter(|f| !relative_data_path(dir, f).is_ok_and(|rel| is_excluded(&rel, DEFAULT_EXCLUDED_PATTERNS)))
+        .filter_map(|f| fs::metadata(f).ok())
+        .map(|m| m.len())
+        .sum();
+    let freed: u64 = std::iter::once(target)
+        .chain(replaced)
+        .filter_map(|p| fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum();
+    let projected = current.saturating_sub(freed) + new_len;
+    if projected > quota.max_bytes {
+        return Err(format!(
+            "Directory quota exceeded for '{}': {} bytes used, write would make {} of {} allowed",
+            dir.display(),
+            current,
+            projected,
+            quota.max_bytes
+        ));
+    }
+    Ok(())
+}
+
+/// Cap the total size of `dirname` (relative to the data dir) at `max_bytes`;
+/// writes that would exceed it fail
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn set_directory_quota(app: tauri::AppHandle, dirname: String, max_bytes: u64) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let dir = resolve_data_path(&data_dir, &dirname)?;
+    let json = serde_json::to_string_pretty(&DirectoryQuota { max_bytes })
+        .map_err(|e| format!("Failed to serialize quota: {}", e))?;
+    atomic_write(&dir.join(QUOTA_FILE), json.as_bytes())
+        .map_err(|e| format!("Failed to write quota for '{}': {}", dirname, e))
+}
+
+/// Write base64-encoded binary content. Free space is checked against the decoded
+/// size before anything touches disk, and the write goes through a temp file.
+/// The filename is sanitized as in `write_data_file`.
+#[tauri::command]
+#[tracing::instrument(skip(app, content), err)]
+fn write_data_file_binary(app: tauri::AppHandle, filename: String, content: String) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let filename = sanitize_write_path(&data_dir, &filename)?;
+    ensure_writable(&app, &filename)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let bytes = general_purpose::STANDARD
+        .decode(content.as_bytes())
+        .map_err(|e| format!("Invalid base64 content for '{}': {}", filename, e))?;
+    ensure_free_space(&data_dir, bytes.len() as u64)?;
+    write_within_quota(&data_dir.join(&filename), None, &bytes, false, true, &filename)
+}
+
+/// Internal files hidden from `list_data_files` regardless of caller patterns
+const DEFAULT_EXCLUDED_PATTERNS: &[&str] = &[
+    ".lock",
+    ".write_test",
+    "checksums.json",
+    "deletions.log",
+    "*.auditlog",
+    "*.meta.json",
+    "*.tags.json",
+    PAPERIGNORE_FILE,
+];
+
+/// Match `text` against a glob where `*` is any run of characters and `?` is one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it is currently absorbing up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Patterns with a `/` match the data-dir relative path, others just the file name
+fn is_excluded(rel_path: &str, patterns: &[&str]) -> bool {
+    let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+    patterns.iter().any(|pattern| {
+        let target = if pattern.contains('/') { rel_path } else { name };
+        glob_match(pattern, target)
+    })
+}
+
+/// Re-serialize a JSON note with `indent` spaces and write it back atomically.
+/// Key order is kept; parse errors report the line and column.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn format_json_note(app: tauri::AppHandle, filename: String, indent: usize) -> Result<(), String> {
+    use serde::Serialize;
+
+    ensure_unlocked(&app)?;
+    ensure_writable(&app, &filename)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let path = resolve_data_path(&data_dir, &filename)?;
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    // serde_json's error message already ends in "at line N column M"
+    let value: serde_json::Value = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid JSON in '{}': {}", filename, e))?;
+
+    let indent = " ".repeat(indent);
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut out = Vec::with_capacity(raw.len());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    value
+        .serialize(&mut serializer)
+        .map_err(|e| format!("Failed to serialize '{}': {}", filename, e))?;
+    out.push(b'\n');
+    write_within_quota(&path, None, &out, false, true, &filename)
+}
+
+/// Replacement for each redacted span in `anonymize_note`
+const REDACTION_MARKER: &str = "[REDACTED]";
+
+/// Always redacted by `anonymize_note`, on top of the caller's patterns
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+
+/// Write a copy of a note to `dest_filename` with every match of `patterns`
+/// (regexes) and every email address replaced by `[REDACTED]`. Overlapping
+/// matches are redacted once. Returns the number of redactions; the original
+/// is left untouched.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn anonymize_note(
+    app: tauri::AppHandle,
+    filename: String,
+    dest_filename: String,
+    patterns: Vec<String>,
+) -> Result<usize, String> {
+    ensure_unlocked(&app)?;
+    ensure_writable(&app, &dest_filename)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let (root, relative) = read_root(&app, data_dir.clone(), &filename)?;
+    // Compare resolved paths so spellings like `./a.md` can't overwrite the original
+    if resolve_data_path(&data_dir, &dest_filename)? == resolve_data_path(&root, &relative)? {
+        return Err("Destination must differ from the original note".to_string());
+    }
+    let content = read_data_file_in(&root, &relative)?;
+    let (redacted, count) = anonymize_text(&content, &patterns)?;
+    write_data_file_in(&data_dir, &dest_filename, &redacted, false, false, true)?;
+    Ok(count)
+}
+
+fn anonymize_text(content: &str, patterns: &[String]) -> Result<(String, usize), String> {
+    let mut regexes = vec![regex::Regex::new(EMAIL_PATTERN).map_err(|e| e.to_string())?];
+    for pattern in patterns {
+        regexes.push(
+            regex::Regex::new(pattern).map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?,
+        );
+    }
+    let mut spans: Vec<(usize, usize)> = regexes
+        .iter()
+        .flat_map(|re| re.find_iter(content))
+        .filter(|m| !m.is_empty())
+        .map(|m| (m.start(), m.end()))
+        .collect();
+    spans.sort_unstable();
+
+    let mut redacted = String::with_capacity(content.len());
+    let mut count = 0;
+    let mut pos = 0;
+    for (start, end) in spans {
+        if end <= pos {
+            continue;
+        }
+        if start >= pos {
+            redacted.push_str(&content[pos..start]);
+            redacted.push_str(REDACTION_MARKER);
+            count += 1;
+        }
+        // An overlapping span extends the redaction already written
+        pos = end;
+    }
+    redacted.push_str(&content[pos..]);
+    Ok((redacted, count))
+}
+
+/// Base64 image data URIs, capturing the MIME subtype and the payload
+const DATA_URI_PATTERN: &str = r"data:image/([A-Za-z0-9.+-]+)(?:;[A-Za-z0-9.+=-]+)*;base64,([A-Za-z0-9+/]+={0,2})";
+
+#[derive(serde::Serialize)]
+struct DataUriExtraction {
+    /// References rewritten, counting repeats of the same image
+    extracted: usize,
+    /// How much smaller the note got; negative if tiny images became longer paths
+    bytes_saved: i64,
+    /// Image files written, relative to the data dir
+    images: Vec<String>,
+}
+
+/// Move every base64 image data URI in a note into a file under
+/// `<note stem>_images/` next to it, rewrite the reference to that relative
+/// path and write the slimmer note back atomically. Images are named by
+/// content hash, so repeats share one file. URIs with an unknown image type or
+/// invalid base64 are left in place.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn extract_note_data_uris(app: tauri::AppHandle, filename: String) -> Result<DataUriExtraction, String> {
+    ensure_unlocked(&app)?;
+    ensure_writable(&app, &filename)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    run_limited(&app, move || extract_note_data_uris_in(&data_dir, &filename)).await
+}
+
+fn extract_note_data_uris_in(data_dir: &Path, filename: &str) -> Result<DataUriExtraction, String> {
+    use sha2::Digest;
+
+    resolve_data_path(data_dir, filename)?;
+    let content = read_data_file_in(data_dir, filename)?;
+    let note = Path::new(filename);
+    let stem = note.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let image_dir = format!("{}_images", stem);
+    let note_dir = note.parent().unwrap_or(Path::new(""));
+
+    let re = regex::Regex::new(DATA_URI_PATTERN).map_err(|e| e.to_string())?;
+    let mut images = Vec::new();
+    let mut extracted = 0;
+    let slimmed = re.replace_all(&content, |caps: &regex::Captures| {
+        let ext = match caps[1].to_ascii_lowercase().as_str() {
+            "png" => "png",
+            "jpeg" | "jpg" | "pjpeg" => "jpg",
+            "gif" => "gif",
+            "webp" => "webp",
+            "svg+xml" => "svg",
+            "bmp" => "bmp",
+            "avif" => "avif",
+            "tiff" => "tiff",
+            "x-icon" | "vnd.microsoft.icon" => "ico",
+            _ => return caps[0].to_string(),
+        };
+        let Ok(bytes) = general_purpose::STANDARD.decode(&caps[2]) else {
+            return caps[0].to_string();
+        };
+        let name = format!("{}.{}", &hex_encode(&sha2::Sha256::digest(&bytes))[..16], ext);
+        let relative = note_dir.join(&image_dir).join(&name).to_string_lossy().replace('\\', "/");
+        if !images.contains(&relative) {
+            let path = data_dir.join(&relative);
+            if !path.is_file() {
+                if let Err(e) = write_within_quota(&path, None, &bytes, false, true, &relative) {
+                    log::warn!("[extract_note_data_uris] Keeping an image inline: {}", e);
+                    return caps[0].to_string();
+                }
+            }
+            images.push(relative);
+        }
+        extracted += 1;
+        format!("{}/{}", urlencoding_encode(&image_dir), name)
+    });
+
+    let bytes_saved = content.len() as i64 - slimmed.len() as i64;
+    if extracted > 0 {
+        let compressed = data_dir.join(format!("{}{}", filename, COMPRESSED_SUFFIX)).exists();
+        write_data_file_in(data_dir, filename, &slimmed, compressed || filename.ends_with(".gz"), false, true)?;
+    }
+    Ok(DataUriExtraction { extracted, bytes_saved, images })
+}
+
+/// Gitignore-style patterns in the data dir root that hide files from `list_data_files`
+const PAPERIGNORE_FILE: &str = ".paperignore";
+
+/// Matcher for `.paperignore`, empty when there is none. Bad lines are logged and
+/// skipped so one typo doesn't disable the rest.
+fn load_paperignore(data_dir: &Path) -> ignore::gitignore::Gitignore {
+    let path = data_dir.join(PAPERIGNORE_FILE);
+    if !path.is_file() {
+        return ignore::gitignore::Gitignore::empty();
+    }
+    let mut builder = ignore::gitignore::GitignoreBuilder::new(data_dir);
+    if let Some(e) = builder.add(&path) {
+        log::warn!("[list_data_files] Problem in {}: {}", PAPERIGNORE_FILE, e);
+    }
+    builder.build().unwrap_or_else(|e| {
+        log::warn!("[list_data_files] Ignoring {}: {}", PAPERIGNORE_FILE, e);
+        ignore::gitignore::Gitignore::empty()
+    })
+}
+
+/// Directory outside the data dir whose files are listed and read under a virtual prefix
+struct VfsOverlay {
+    /// Ends with `/`, e.g. `cloud/`
+    prefix: String,
+    root: PathBuf,
+}
+
+/// Overlays registered with `add_vfs_overlay`; they last for the session
+#[derive(Default)]
+struct VfsOverlays(std::sync::Mutex<Vec<VfsOverlay>>);
+
+impl VfsOverlays {
+    /// Overlay root and the path inside it, if `filename` falls under an overlay prefix
+    fn route(&self, filename: &str) -> Option<(PathBuf, String)> {
+        let filename = filename.replace('\\', "/");
+        let overlays = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        overlays
+            .iter()
+            .filter(|overlay| filename.starts_with(&overlay.prefix))
+            .max_by_key(|overlay| overlay.prefix.len())
+            .map(|overlay| (overlay.root.clone(), filename[overlay.prefix.len()..].to_string()))
+    }
+}
+
+/// Mount `overlay_dir` read-only under `prefix` (e.g. `cloud/`): `list_data_files`
+/// includes its files under the prefix and the read commands serve them, while
+/// writes to the prefix fail. Registering a prefix again replaces its directory.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn add_vfs_overlay(app: tauri::AppHandle, overlay_dir: String, prefix: String) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let root = fs::canonicalize(&overlay_dir)
+        .map_err(|e| format!("Failed to open overlay '{}': {}", overlay_dir, e))?;
+    if !root.is_dir() {
+        return Err(format!("Overlay is not a directory: {}", overlay_dir));
+    }
+    let trimmed = prefix.replace('\\', "/");
+    let trimmed = trimmed.trim_matches('/');
+    let normalized = normalize_relative_path(Path::new(trimmed))
+        .filter(|p| !p.as_os_str().is_empty())
+        .ok_or_else(|| format!("Invalid overlay prefix: {}", prefix))?;
+    let prefix = format!("{}/", normalized.to_string_lossy().replace('\\', "/"));
+
+    let overlays = app.state::<VfsOverlays>();
+    let mut overlays = overlays.0.lock().unwrap_or_else(|e| e.into_inner());
+    overlays.retain(|overlay| overlay.prefix != prefix);
+    overlays.push(VfsOverlay { prefix, root });
+    app.state::<ListCache>().invalidate();
+    Ok(())
+}
+
+/// Directory and path within it that a read of `filename` goes to: an overlay
+/// for overlay paths, otherwise the data dir. Paths escaping either are refused.
+fn read_root(app: &tauri::AppHandle, data_dir: PathBuf, filename: &str) -> Result<(PathBuf, String), String> {
+    match app.state::<VfsOverlays>().route(filename) {
+        Some((root, relative)) => {
+            resolve_data_path(&root, &relative)?;
+            Ok((root, relative))
+        }
+        None => {
+            resolve_data_path(&data_dir, filename)?;
+            Ok((data_dir, filename.to_string()))
+        }
+    }
+}
+
+/// Overlays are read-only
+fn ensure_writable(app: &tauri::AppHandle, filename: &str) -> Result<(), String> {
+    if app.state::<VfsOverlays>().route(filename).is_some() {
+        return Err("read-only overlay".to_string());
+    }
+    Ok(())
+}
+
+/// List data files, minus internal files (dot-files such as the index, blobs,
+/// backups and temp files, `cache/`, `data_version` and
+/// `DEFAULT_EXCLUDED_PATTERNS`), `exclude_patterns` and anything matched by
+/// `.paperignore` in the data dir. That file uses gitignore syntax, one pattern
+/// per line, relative to the data dir:
+///
+/// - `*.aux`, `build/`: `*` and `?` don't cross `/`; a trailing `/` matches directories only
+/// - `/draft.tex`: a leading `/` anchors to the data dir root, otherwise any depth matches
+/// - `**/cache/**`: `**` spans any number of directories
+/// - `!keep.aux`: a leading `!` re-includes a path an earlier pattern excluded
+/// - `# note`: comment; blank lines are ignored
+///
+/// Files in overlays (see `add_vfs_overlay`) follow, under their prefixes;
+/// `.paperignore` doesn't apply to them.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn list_data_files(
+    app: tauri::AppHandle,
+    exclude_patterns: Option<Vec<String>>,
+) -> Result<Vec<String>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let mut patterns: Vec<&str> = DEFAULT_EXCLUDED_PATTERNS.to_vec();
+    patterns.extend(exclude_patterns.iter().flatten().map(String::as_str));
+    let paperignore = load_paperignore(&data_dir);
+    let mut files = Vec::new();
+    fn walk(
+        dir: &PathBuf,
+        base: &PathBuf,
+        patterns: &[&str],
+        paperignore: &ignore::gitignore::Gitignore,
+        skip_helpers: bool,
+        files: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read directory: {}", e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Dir entry error: {}", e))?;
+            if skip_helpers && is_helper_entry(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let path = entry.path();
+            let is_dir = path.is_dir();
+            if paperignore.matched(&path, is_dir).is_ignore() {
+                continue;
+            }
+            if is_dir {
+                walk(&path, base, patterns, paperignore, skip_helpers, files)?;
+            } else if path.is_file() {
+                let rel = path.strip_prefix(base)
+                    .map_err(|e| format!("Path error: {}", e))?;
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                if !is_excluded(&rel, patterns) {
+                    files.push(rel);
+                }
+            }
+        }
+        Ok(())
+    }
+    walk(&data_dir, &data_dir, &patterns, &paperignore, true, &mut files)?;
+
+    let overlays: Vec<(String, PathBuf)> = {
+        let overlays = app.state::<VfsOverlays>();
+        let overlays = overlays.0.lock().unwrap_or_else(|e| e.into_inner());
+        overlays.iter().map(|o| (o.prefix.clone(), o.root.clone())).collect()
+    };
+    let no_ignore = ignore::gitignore::Gitignore::empty();
+    for (prefix, root) in overlays {
+        let mut overlay_files = Vec::new();
+        // An unmounted archive shouldn't hide the rest of the listing
+        if let Err(e) = walk(&root, &root, &patterns, &no_ignore, false, &mut overlay_files) {
+            log::warn!("[list_data_files] Skipping overlay '{}': {}", prefix, e);
+            continue;
+        }
+        files.extend(overlay_files.into_iter().map(|f| format!("{}{}", prefix, f)));
+    }
+    Ok(files)
+}
+
+/// `list_data_files` in natural order: runs of digits compare by value, so
+/// `fig2.png` comes before `fig10.png`, and letters ignore case
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn list_data_files_sorted(
+    app: tauri::AppHandle,
+    exclude_patterns: Option<Vec<String>>,
+) -> Result<Vec<String>, String> {
+    let mut files = list_data_files(app, exclude_patterns)?;
+    files.sort_by(|a, b| natural_cmp(a, b));
+    Ok(files)
+}
+
+/// Natural ordering for file names. Names that differ only in case or leading
+/// zeros fall back to plain byte order, so the ordering is total and sorts are
+/// reproducible.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    let (mut x, mut y) = (a, b);
+    loop {
+        let (c, d) = match (x.chars().next(), y.chars().next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(c), Some(d)) => (c, d),
+        };
+        if c.is_ascii_digit() && d.is_ascii_digit() {
+            let x_end = x.find(|c: char| !c.is_ascii_digit()).unwrap_or(x.len());
+            let y_end = y.find(|c: char| !c.is_ascii_digit()).unwrap_or(y.len());
+            let (m, n) = (x[..x_end].trim_start_matches('0'), y[..y_end].trim_start_matches('0'));
+            // Without leading zeros, the longer run is the larger number
+            let ordering = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            x = &x[x_end..];
+            y = &y[y_end..];
+        } else {
+            let ordering = c.to_lowercase().cmp(d.to_lowercase());
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            x = &x[c.len_utf8()..];
+            y = &y[d.len_utf8()..];
+        }
+    }
+}
+
+/// How long `list_data_files_cached` reuses a listing by default
+const LIST_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Last `list_data_files` result, for `list_data_files_cached`
+struct ListCache(std::sync::Mutex<ListCacheState>);
+
+struct ListCacheState {
+    files: Option<(std::time::Instant, Vec<String>)>,
+    ttl: std::time::Duration,
+    /// Bumped by every invalidation, so a listing that was already running
+    /// when a file changed isn't stored
+    generation: u64,
+}
+
+impl Default for ListCache {
+    fn default() -> Self {
+        ListCache(std::sync::Mutex::new(ListCacheState { files: None, ttl: LIST_CACHE_TTL, generation: 0 }))
+    }
+}
+
+impl ListCache {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ListCacheState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn invalidate(&self) {
+        let mut state = self.lock();
+        state.files = None;
+        state.generation += 1;
+    }
+}
+
+/// `list_data_files` with the default exclusions, reusing the previous result
+/// for a few seconds (see `set_list_cache_ttl`). Every write through
+/// `write_within_quota`, and deletes and renames through this app, clear the
+/// cache; call `invalidate_list_cache` after changing the data dir any other way.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn list_data_files_cached(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    ensure_unlocked(&app)?;
+    let generation = {
+        let state = app.state::<ListCache>();
+        let state = state.lock();
+        if let Some((listed_at, files)) = &state.files {
+            if listed_at.elapsed() < state.ttl {
+                return Ok(files.clone());
+            }
+        }
+        state.generation
+    };
+    let files = list_data_files(app.clone(), None)?;
+    let cache = app.state::<ListCache>();
+    let mut state = cache.lock();
+    if state.generation == generation {
+        state.files = Some((std::time::Instant::now(), files.clone()));
+    }
+    Ok(files)
+}
+
+/// Drop the cached listing so the next `list_data_files_cached` walks the data dir
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn invalidate_list_cache(app: tauri::AppHandle) {
+    app.state::<ListCache>().invalidate();
+}
+
+/// Change how long `list_data_files_cached` reuses a listing; 0 disables caching
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn set_list_cache_ttl(app: tauri::AppHandle, ttl_ms: u64) {
+    let cache = app.state::<ListCache>();
+    let mut state = cache.lock();
+    state.ttl = std::time::Duration::from_millis(ttl_ms);
+    state.files = None;
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+enum OutlineFormat {
+    Opml,
+    /// Nested bullet list, folders ending in `/`
+    Markdown,
+}
+
+/// One folder of the library outline, children sorted by name
+#[derive(Default)]
+struct OutlineDir {
+    dirs: std::collections::BTreeMap<String, OutlineDir>,
+    /// Name and size in bytes
+    files: std::collections::BTreeMap<String, u64>,
+}
+
+/// Write the data dir's folder structure to `dest_path` as OPML (with each
+/// file's size in a `size` attribute) or a markdown nested list. Folders come
+/// before files; the files `list_data_files` hides are left out, and so are
+/// folders with nothing else in them. Returns the number of files written.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn export_tree_outline(app: tauri::AppHandle, dest_path: String, format: OutlineFormat) -> Result<usize, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let (outline, count) = tree_outline_in(&data_dir, format)?;
+    atomic_write(Path::new(&dest_path), outline.as_bytes())
+        .map_err(|e| format!("Failed to write '{}': {}", dest_path, e))?;
+    Ok(count)
+}
+
+fn tree_outline_in(data_dir: &Path, format: OutlineFormat) -> Result<(String, usize), String> {
+    let paperignore = load_paperignore(data_dir);
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    let mut root = OutlineDir::default();
+    let mut count = 0;
+    for path in files {
+        let rel = relative_data_path(data_dir, &path)?;
+        if is_excluded(&rel, DEFAULT_EXCLUDED_PATTERNS)
+            || paperignore.matched_path_or_any_parents(&path, false).is_ignore()
+        {
+            continue;
+        }
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let mut parts: Vec<&str> = rel.split('/').collect();
+        let Some(name) = parts.pop() else {
+            continue;
+        };
+        let dir = parts.into_iter().fold(&mut root, |dir, part| dir.dirs.entry(part.to_string()).or_default());
+        dir.files.insert(name.to_string(), size);
+        count += 1;
+    }
+
+    let mut out = String::new();
+    match format {
+        OutlineFormat::Opml => {
+            let title = data_dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n");
+            out.push_str(&format!("  <head>\n    <title>{}</title>\n  </head>\n  <body>\n", xml_escape(&title)));
+            write_opml_outline(&root, 2, &mut out);
+            out.push_str("  </body>\n</opml>\n");
+        }
+        OutlineFormat::Markdown => write_markdown_outline(&root, 0, &mut out),
+    }
+    Ok((out, count))
+}
+
+fn write_opml_outline(dir: &OutlineDir, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for (name, child) in &dir.dirs {
+        out.push_str(&format!("{}<outline text=\"{}\" type=\"folder\">\n", indent, xml_escape(name)));
+        write_opml_outline(child, depth + 1, out);
+        out.push_str(&format!("{}</outline>\n", indent));
+    }
+    for (name, size) in &dir.files {
+        out.push_str(&format!("{}<outline text=\"{}\" type=\"file\" size=\"{}\"/>\n", indent, xml_escape(name), size));
+    }
+}
+
+fn write_markdown_outline(dir: &OutlineDir, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for (name, child) in &dir.dirs {
+        out.push_str(&format!("{}- {}/\n", indent, name));
+        write_markdown_outline(child, depth + 1, out);
+    }
+    for name in dir.files.keys() {
+        out.push_str(&format!("{}- {}\n", indent, name));
+    }
+}
+
+/// Escape text for use in XML content and double-quoted attributes
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Join a caller-supplied relative path onto the data dir, rejecting anything
+/// (absolute paths, `..`) that could point outside it
+fn resolve_data_path(data_dir: &Path, relative: &str) -> Result<PathBuf, String> {
+    use std::path::Component;
+
+    let mut resolved = data_dir.to_path_buf();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            _ => return Err(format!("Path escapes the data directory: {}", relative)),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Entries the app keeps for its own bookkeeping (caches, index, version marker)
+fn is_helper_entry(name: &str) -> bool {
+    name.starts_with('.') || name == "cache" || name == DATA_VERSION_FILE
+}
+
+/// Recursively collect file paths under `dir`, optionally skipping helper entries
+fn collect_files(dir: &Path, skip_helpers: bool, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read directory: {}", e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Dir entry error: {}", e))?;
+        if skip_helpers && is_helper_entry(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, skip_helpers, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Data-dir relative path with forward slashes
+fn relative_data_path(data_dir: &Path, path: &Path) -> Result<String, String> {
+    let rel = path.strip_prefix(data_dir)
+        .map_err(|e| format!("Path error: {}", e))?;
+    Ok(rel.to_string_lossy().replace('\\', "/"))
+}
+
+fn modified_millis(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(serde::Serialize)]
+struct FolderStats {
+    file_count: u64,
+    total_size_bytes: u64,
+    /// Newest file mtime, or the folder's own mtime when it has no files
+    last_modified_millis: u64,
+}
+
+/// Recursive file count, size and last-modified time for a folder in the data dir
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_folder_stats(app: tauri::AppHandle, relative_dir: String) -> Result<FolderStats, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let dir = resolve_data_path(&data_dir, &relative_dir)?;
+    if !dir.is_dir() {
+        return Err(format!("Not a folder: {}", relative_dir));
+    }
+
+    let mut paths = Vec::new();
+    collect_files(&dir, true, &mut paths)?;
+    let mut stats = FolderStats {
+        file_count: 0,
+        total_size_bytes: 0,
+        last_modified_millis: 0,
+    };
+    for path in &paths {
+        let metadata = fs::metadata(path)
+            .map_err(|e| format!("Failed to stat '{}': {}", path.display(), e))?;
+        stats.file_count += 1;
+        stats.total_size_bytes += metadata.len();
+        stats.last_modified_millis = stats.last_modified_millis.max(modified_millis(&metadata));
+    }
+    if stats.file_count == 0 {
+        let metadata = fs::metadata(&dir)
+            .map_err(|e| format!("Failed to stat '{}': {}", relative_dir, e))?;
+        stats.last_modified_millis = modified_millis(&metadata);
+    }
+    Ok(stats)
+}
+
+#[derive(serde::Serialize)]
+struct ChangedFile {
+    path: String,
+    modified_millis: u64,
+}
+
+/// Files modified after `timestamp_millis`, oldest first, for incremental sync
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn list_changed_since(app: tauri::AppHandle, timestamp_millis: u64) -> Result<Vec<ChangedFile>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let mut paths = Vec::new();
+    collect_files(&data_dir, true, &mut paths)?;
+
+    let mut changed = Vec::new();
+    for path in paths {
+        let metadata = fs::metadata(&path)
+            .map_err(|e| format!("Failed to stat '{}': {}", path.display(), e))?;
+        let mtime = modified_millis(&metadata);
+        if mtime > timestamp_millis {
+            changed.push(ChangedFile {
+                path: relative_data_path(&data_dir, &path)?,
+                modified_millis: mtime,
+            });
+        }
+    }
+    changed.sort_by_key(|f| f.modified_millis);
+    Ok(changed)
+}
+
+#[derive(serde::Serialize)]
+struct FileMetadata {
+    path: String,
+    size_bytes: u64,
+    modified_millis: u64,
+    mime_type: String,
+    /// Number of `\n` bytes, for text files up to 10 MB; `None` for larger
+    /// files, binary files and listings made with `extensions_only`
+    line_count: Option<u64>,
+}
+
+/// Largest file `FileMetadata::line_count` is counted for
+const LINE_COUNT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Like `list_data_files`, but with size, mtime and MIME type per file. MIME types
+/// come from magic bytes with an extension fallback; `extensions_only` skips
+/// opening files for speed. Helper files (index, caches) are left out.
+///
+/// Results are kept in the `file_metadata` table of the index; files whose size
+/// and mtime are unchanged since the last call are served from there.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn list_data_files_with_metadata(
+    app: tauri::AppHandle,
+    extensions_only: Option<bool>,
+) -> Result<Vec<FileMetadata>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    indexed_file_metadata(&data_dir, extensions_only.unwrap_or(false))
+}
+
+/// Size, mtime, sniffed MIME type and line count of one data file
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_data_file_metadata(app: tauri::AppHandle, filename: String) -> Result<FileMetadata, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let path = resolve_data_path(&data_dir, &filename)?;
+    let metadata = fs::metadata(&path)
+        .map_err(|e| format!("Failed to stat '{}': {}", filename, e))?;
+    Ok(scan_file_metadata(&path, relative_data_path(&data_dir, &path)?, &metadata, false))
+}
+
+/// Drop the cached file metadata and rescan (with MIME sniffing); returns the file count
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn rebuild_index(app: tauri::AppHandle) -> Result<u32, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    open_index(&data_dir)?
+        .execute("DELETE FROM file_metadata", [])
+        .map_err(|e| format!("Failed to clear index: {}", e))?;
+    Ok(indexed_file_metadata(&data_dir, false)?.len() as u32)
+}
+
+/// Cached row: size, mtime, MIME type, whether the type was sniffed and line count
+type CachedFileMetadata = (u64, u64, String, bool, Option<u64>);
+
+fn indexed_file_metadata(data_dir: &Path, extensions_only: bool) -> Result<Vec<FileMetadata>, String> {
+    use rayon::prelude::*;
+
+    let mut conn = open_index(data_dir)?;
+    let mut cached: std::collections::HashMap<String, CachedFileMetadata> = std::collections::HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare("SELECT path, size_bytes, modified_millis, mime_type, sniffed, line_count FROM file_metadata")
+            .map_err(|e| format!("Failed to read index: {}", e))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    (
+                        row.get::<_, i64>(1)? as u64,
+                        row.get::<_, i64>(2)? as u64,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get::<_, Option<i64>>(5)?.map(|n| n as u64),
+                    ),
+                ))
+            })
+            .map_err(|e| format!("Failed to read index: {}", e))?;
+        for row in rows {
+            let (path, entry) = row.map_err(|e| format!("Failed to read index: {}", e))?;
+            cached.insert(path, entry);
+        }
+    }
+
+    let mut paths = Vec::new();
+    collect_files(data_dir, true, &mut paths)?;
+    // (metadata, whether the type was sniffed, whether the row needs writing)
+    let scanned: Vec<(FileMetadata, bool, bool)> = paths
+        .par_iter()
+        .map(|path| {
+            let metadata = fs::metadata(path)
+                .map_err(|e| format!("Failed to stat '{}': {}", path.display(), e))?;
+            let rel = relative_data_path(data_dir, path)?;
+            let size_bytes = metadata.len();
+            let modified = modified_millis(&metadata);
+            if let Some((size, mtime, mime, sniffed, line_count)) = cached.get(&rel) {
+                if *size == size_bytes && *mtime == modified && (*sniffed || extensions_only) {
+                    let entry = FileMetadata {
+                        path: rel,
+                        size_bytes,
+                        modified_millis: modified,
+                        mime_type: mime.clone(),
+                        line_count: *line_count,
+                    };
+                    return Ok((entry, *sniffed, false));
+                }
+            }
+            Ok((scan_file_metadata(path, rel, &metadata, extensions_only), !extensions_only, true))
+        })
+        .collect::<Result<_, String>>()?;
+
+    let tx = conn.transaction().map_err(|e| format!("Failed to update index: {}", e))?;
+    for (entry, sniffed, changed) in &scanned {
+        cached.remove(&entry.path);
+        if *changed {
+            tx.execute(
+                "INSERT OR REPLACE INTO file_metadata (path, size_bytes, modified_millis, mime_type, sniffed, line_count)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    entry.path,
+                    entry.size_bytes as i64,
+                    entry.modified_millis as i64,
+                    entry.mime_type,
+                    sniffed,
+                    entry.line_count.map(|n| n as i64),
+                ],
+            )
+            .map_err(|e| format!("Failed to update index: {}", e))?;
+        }
+    }
+    // Whatever is left in `cached` no longer exists on disk
+    for path in cached.keys() {
+        tx.execute("DELETE FROM file_metadata WHERE path = ?1", [path])
+            .map_err(|e| format!("Failed to update index: {}", e))?;
+    }
+    tx.commit().map_err(|e| format!("Failed to update index: {}", e))?;
+    Ok(scanned.into_iter().map(|(entry, _, _)| entry).collect())
+}
+
+/// Metadata read from the file itself; `extensions_only` goes by the name alone
+fn scan_file_metadata(path: &Path, rel: String, metadata: &fs::Metadata, extensions_only: bool) -> FileMetadata {
+    let sniffed = if extensions_only { None } else { sniff_mime_type(path) };
+    // A recognized signature means a binary format
+    let line_count = if extensions_only || sniffed.is_some() || metadata.len() > LINE_COUNT_MAX_BYTES {
+        None
+    } else {
+        count_lines(path)
+    };
+    FileMetadata {
+        path: rel,
+        size_bytes: metadata.len(),
+        modified_millis: modified_millis(metadata),
+        mime_type: sniffed.unwrap_or_else(|| mime_type_from_extension(path)).to_string(),
+        line_count,
+    }
+}
+
+/// `\n` bytes in a file, streamed; `None` if it can't be read or contains a
+/// NUL byte, which text never does
+fn count_lines(path: &Path) -> Option<u64> {
+    use std::io::BufRead;
+
+    let mut reader = std::io::BufReader::new(fs::File::open(path).ok()?);
+    let mut lines = 0;
+    loop {
+        let buf = reader.fill_buf().ok()?;
+        if buf.is_empty() {
+            return Some(lines);
+        }
+        if buf.contains(&0) {
+            return None;
+        }
+        lines += buf.iter().filter(|&&b| b == b'\n').count() as u64;
+        let consumed = buf.len();
+        reader.consume(consumed);
+    }
+}
+
+/// MIME type from the first 16 bytes, for formats with a reliable signature
+fn sniff_mime_type(path: &Path) -> Option<&'static str> {
+    use std::io::Read;
+
+    let mut header = [0u8; 16];
+    let mut file = fs::File::open(path).ok()?;
+    let n = file.read(&mut header).ok()?;
+    let header = &header[..n];
+
+    let mime = if header.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if header.len() >= 12 && header.starts_with(b"RIFF") && &header[8..12] == b"WEBP" {
+        "image/webp"
+    } else if header.starts_with(&[0x1F, 0x8B]) {
+        "application/gzip"
+    } else if header.starts_with(b"PK\x03\x04") {
+        // EPUB and friends are zip containers; trust the extension for the specific type
+        match mime_type_from_extension(path) {
+            "application/octet-stream" => "application/zip",
+            ext_mime => ext_mime,
+        }
+    } else {
+        return None;
+    };
+    Some(mime)
+}
+
+fn mime_type_from_extension(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "pdf" => "application/pdf",
+        "html" | "htm" => "text/html",
+        "md" | "markdown" => "text/markdown",
+        "txt" => "text/plain",
+        "json" => "application/json",
+        "jsonl" => "application/x-ndjson",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "css" => "text/css",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "epub" => "application/epub+zip",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn check_data_file_exists(app: tauri::AppHandle, filename: String) -> Result<bool, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    Ok(stored_data_path(&root, &relative)?.0.exists())
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FileDeletedEvent {
+    filename: String,
+    timestamp: u64,
+}
+
+fn emit_file_deleted(app: &tauri::AppHandle, filename: &str) {
+    use tauri::Emitter;
+
+    let event = FileDeletedEvent { filename: filename.to_string(), timestamp: now_millis() };
+    if let Err(e) = app.emit("file-deleted", event) {
+        log::warn!("[delete_data_file] Failed to emit file-deleted: {}", e);
+    }
+}
+
+/// Files the frontend currently displays, so they aren't deleted out from under the webview
+#[derive(Default)]
+struct OpenFiles(std::sync::Mutex<std::collections::HashSet<String>>);
+
+impl OpenFiles {
+    fn key(filename: &str) -> String {
+        filename.replace('\\', "/")
+    }
+
+    fn contains(&self, filename: &str) -> bool {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).contains(&Self::key(filename))
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn mark_file_open(app: tauri::AppHandle, filename: String) {
+    let open = app.state::<OpenFiles>();
+    open.0.lock().unwrap_or_else(|e| e.into_inner()).insert(OpenFiles::key(&filename));
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app))]
+fn mark_file_closed(app: tauri::AppHandle, filename: String) {
+    let open = app.state::<OpenFiles>();
+    open.0.lock().unwrap_or_else(|e| e.into_inner()).remove(&OpenFiles::key(&filename));
+}
+
+/// Delete a file and emit `file-deleted`. Missing files are not an error and emit nothing.
+/// Files marked open with `mark_file_open` are refused unless `force` is set.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn delete_data_file(app: tauri::AppHandle, filename: String, force: Option<bool>) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    delete_data_file_checked(&app, &data_dir, &filename, force.unwrap_or(false))?;
+    Ok(())
+}
+
+/// One delete with the overlay, path and open-file checks; on success the
+/// listing cache is cleared and `file-deleted` emitted. Returns whether a file
+/// was actually removed.
+fn delete_data_file_checked(
+    app: &tauri::AppHandle,
+    data_dir: &Path,
+    filename: &str,
+    force: bool,
+) -> Result<bool, String> {
+    ensure_writable(app, filename)?;
+    if !force && app.state::<OpenFiles>().contains(filename) {
+        return Err(format!("File is currently open: {}", filename));
+    }
+    let removed = delete_data_file_in(data_dir, filename)?;
+    if removed {
+        app.state::<ListCache>().invalidate();
+        emit_file_deleted(app, filename);
+    }
+    Ok(removed)
+}
+
+/// Returns whether a file was actually removed
+fn delete_data_file_in(data_dir: &Path, filename: &str) -> Result<bool, String> {
+    let (path, _) = stored_data_path(data_dir, filename)?;
+    if !path.exists() {
+        return Ok(false);
+    }
+    // Opening for write doesn't modify the file but fails if another process
+    // holds it open, which would otherwise surface as a cryptic OS error. Unix
+    // can unlink open and read-only files, so the check would only misfire there.
+    #[cfg(windows)]
+    if let Err(e) = fs::OpenOptions::new().write(true).open(&path) {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            return Err("File is locked by another process".to_string());
+        }
+    }
+    fs::remove_file(&path)
+        .map_err(|e| format!("Failed to delete file: {}", e))?;
+    Ok(true)
+}
+
+#[derive(serde::Serialize)]
+struct FailedDelete {
+    filename: String,
+    error: String,
+}
+
+#[derive(serde::Serialize)]
+struct BatchDeleteResult {
+    deleted: Vec<String>,
+    failed: Vec<FailedDelete>,
+}
+
+/// Delete several files, continuing past failures. Each file goes through the
+/// same checks as `delete_data_file`: open files are reported as failures
+/// unless `force` is set. Each removed file gets its own `file-deleted` event.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn batch_delete_data_files(
+    app: tauri::AppHandle,
+    filenames: Vec<String>,
+    force: Option<bool>,
+) -> Result<BatchDeleteResult, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let force = force.unwrap_or(false);
+    let mut result = BatchDeleteResult { deleted: Vec::new(), failed: Vec::new() };
+    for filename in filenames {
+        match delete_data_file_checked(&app, &data_dir, &filename, force) {
+            Ok(true) => result.deleted.push(filename),
+            Ok(false) => {}
+            Err(error) => result.failed.push(FailedDelete { filename, error }),
+        }
+    }
+    Ok(result)
+}
+
+/// Exchange two files' contents via a three-way rename through a temp name,
+/// rolling back if any step fails. Their tag and metadata sidecars and index
+/// records swap with them, so tags, pins and summaries follow the content.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn swap_data_files(app: tauri::AppHandle, path_a: String, path_b: String) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    ensure_writable(&app, &path_a)?;
+    ensure_writable(&app, &path_b)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let a = resolve_data_path(&data_dir, &path_a)?;
+    let b = resolve_data_path(&data_dir, &path_b)?;
+    if a == b {
+        return Err("Cannot swap a file with itself".to_string());
+    }
+    for (path, name) in [(&a, &path_a), (&b, &path_b)] {
+        if !path.is_file() {
+            return Err(format!("File does not exist: {}", name));
+        }
+    }
+
+    let tmp = a.with_file_name(format!(".swap-{}-{}.tmp", std::process::id(), now_millis()));
+    swap_paths(&a, &b, &tmp).map_err(|e| format!("Failed to swap '{}' and '{}': {}", path_a, path_b, e))?;
+    app.state::<ListCache>().invalidate();
+
+    let rel_a = relative_data_path(&data_dir, &a)?;
+    let rel_b = relative_data_path(&data_dir, &b)?;
+    let rel_tmp = relative_data_path(&data_dir, &tmp)?;
+    for sidecar in [tags_sidecar_path, meta_sidecar_path] {
+        if let Err(e) = swap_paths(&sidecar(&data_dir, &rel_a), &sidecar(&data_dir, &rel_b), &tmp) {
+            log::warn!("[swap_data_files] Failed to swap sidecars: {}", e);
+        }
+    }
+    let records = record_file_rename(&data_dir, &rel_a, &rel_tmp)
+        .and_then(|_| record_file_rename(&data_dir, &rel_b, &rel_a))
+        .and_then(|_| record_file_rename(&data_dir, &rel_tmp, &rel_b));
+    if let Err(e) = records {
+        log::warn!("[swap_data_files] {}", e);
+    }
+    Ok(())
+}
+
+/// Exchange `a` and `b` through `tmp`, rolling back on failure. If only one
+/// exists it is moved to the other's name.
+fn swap_paths(a: &Path, b: &Path, tmp: &Path) -> std::io::Result<()> {
+    match (a.exists(), b.exists()) {
+        (false, false) => return Ok(()),
+        (true, false) => return fs::rename(a, b),
+        (false, true) => return fs::rename(b, a),
+        (true, true) => {}
+    }
+    fs::rename(a, tmp)?;
+    if let Err(e) = fs::rename(b, a) {
+        let _ = fs::rename(tmp, a);
+        return Err(e);
+    }
+    if let Err(e) = fs::rename(tmp, b) {
+        let _ = fs::rename(a, b);
+        let _ = fs::rename(tmp, a);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_data_dir_path(app: tauri::AppHandle) -> Result<String, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(data_dir.to_string_lossy().to_string())
+}
+
+/// Data dir as an `asset://` URL for loading local files in a webview `src`
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_data_dir_asset_url(app: tauri::AppHandle) -> Result<String, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(format!(
+        "asset://localhost/{}/",
+        urlencoding_encode(&data_dir.to_string_lossy())
+    ))
+}
+
+#[derive(serde::Serialize)]
+struct CopyHtmlResult {
+    filename: String,
+    /// Data-dir relative paths of the local assets copied alongside the HTML
+    copied_assets: Vec<String>,
+    timings: CopyHtmlTimings,
+    /// Nothing was written; `filename` and `copied_assets` are what an import would produce
+    dry_run: bool,
+}
+
+/// Wall-clock time spent in each phase of an HTML import
+#[derive(serde::Serialize)]
+struct CopyHtmlTimings {
+    /// Reading the documents and collecting their asset references
+    scan_ms: u64,
+    /// Copying the HTML and its assets
+    copy_ms: u64,
+    /// Rewriting references in the copied documents
+    rewrite_ms: u64,
+}
+
+/// Copy an HTML file to data dir along with the local assets it references
+/// (`<img src>`/`data-src`, `<canvas data-src>` fallbacks, `srcset` candidates
+/// on `<img>` and `<picture>` sources, and `<link rel="icon">`-style favicons). References
+/// to copied assets are rewritten to their normalized relative paths. HTML in
+/// a legacy encoding is stored as UTF-8 with its `<meta>` charset updated.
+///
+/// By default only the top-level file is scanned: HTML pulled in through
+/// `<iframe src>` is neither copied nor scanned, so its images are missed. With
+/// `follow_iframes` those documents are copied and scanned recursively; a
+/// visited set stops iframe cycles (A embeds B embeds A) from looping forever.
+///
+/// With `dest_subdir`, assets land under `<data_dir>/<dest_subdir>/` instead of
+/// next to the HTML, so papers with the same asset names don't overwrite each
+/// other; the HTML's references are rewritten to point there.
+///
+/// With `dry_run`, the documents are scanned and paths resolved as usual but
+/// nothing is copied or rewritten.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn copy_html_with_images(
+    app: tauri::AppHandle,
+    source_path: String,
+    follow_iframes: Option<bool>,
+    dest_subdir: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<CopyHtmlResult, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    tauri::async_runtime::spawn_blocking(move || {
+        copy_html_with_images_into(
+            Path::new(&source_path),
+            &data_dir,
+            follow_iframes.unwrap_or(false),
+            dest_subdir.as_deref(),
+            dry_run.unwrap_or(false),
+        )
+    })
+    .await
+    .map_err(|e| format!("HTML import task failed: {}", e))?
+}
+
+fn copy_html_with_images_into(
+    source: &Path,
+    data_dir: &Path,
+    follow_iframes: bool,
+    dest_subdir: Option<&str>,
+    dry_run: bool,
+) -> Result<CopyHtmlResult, String> {
+    use rayon::prelude::*;
+
+    if !source.exists() {
+        return Err(format!("Source file does not exist: {}", source.display()));
+    }
+    let asset_root = match dest_subdir {
+        Some(subdir) => normalize_relative_path(Path::new(subdir))
+            .map(|subdir| sanitize_relative_path(&subdir))
+            .ok_or_else(|| format!("Invalid destination subdirectory: {}", subdir))?,
+        None => PathBuf::new(),
+    };
+    let source_dir = source.parent().unwrap_or_else(|| std::path::Path::new("."));
+    let filename = import_filename(source)?;
+
+    // Copy the HTML file itself
+    let copy_started = std::time::Instant::now();
+    if !dry_run {
+        copy_within_quota(source, &data_dir.join(&filename), &filename)?;
+    }
+    let mut copy_time = copy_started.elapsed();
+
+    // Read HTML content and extract asset references
+    let scan_started = std::time::Instant::now();
+    let (content, transcoded) = read_html_as_utf8(source).unwrap_or_default();
+    let mut visited = std::collections::HashSet::new();
+    visited.insert(fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf()));
+    let mut assets = Vec::new();
+    let mut documents = Vec::new();
+    collect_html_assets(
+        source_dir,
+        Path::new(""),
+        &asset_root,
+        PathBuf::from(&filename),
+        content,
+        transcoded,
+        follow_iframes,
+        &mut visited,
+        &mut assets,
+        &mut documents,
+    );
+    let scan_time = scan_started.elapsed();
+
+    // Copies are independent, so run them in parallel; collect() keeps document order
+    let assets_started = std::time::Instant::now();
+    // Every collected asset exists, so a dry run reports them all as copied
+    let copied: Vec<PathBuf> = if dry_run {
+        assets
+    } else {
+        assets
+            .into_par_iter()
+            .filter_map(|relative| {
+                let asset_dest = data_dir.join(&asset_root).join(sanitize_relative_path(&relative));
+                match copy_within_quota(&source_dir.join(&relative), &asset_dest, &relative.to_string_lossy()) {
+                    Ok(()) => Some(relative),
+                    Err(e) => {
+                        log::warn!("[copy_html_with_images] {}", e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    };
+    copy_time += assets_started.elapsed();
+
+    // Point references at the paths the assets actually landed on. Nested documents
+    // were copied verbatim above, so this overwrites them too.
+    let rewrite_started = std::time::Instant::now();
+    if dry_run {
+        documents.clear();
+    }
+    for document in documents {
+        let mut edits: Vec<_> = document
+            .edits
+            .into_iter()
+            .filter(|edit| copied.contains(&edit.asset))
+            .collect();
+        // A nested document that failed to copy isn't recreated here
+        if (edits.is_empty() && !document.transcoded) || !data_dir.join(&document.dest).is_file() {
+            continue;
+        }
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+        let mut content = document.content;
+        for edit in edits {
+            content.replace_range(edit.range, &edit.replacement);
+        }
+        let dest = document.dest.to_string_lossy();
+        write_within_quota(&data_dir.join(&document.dest), None, content.as_bytes(), false, true, &dest)?;
+    }
+
+    let copied_assets: Vec<String> = copied
+        .iter()
+        .map(|relative| asset_root.join(sanitize_relative_path(relative)).to_string_lossy().replace('\\', "/"))
+        .collect();
+    let timings = CopyHtmlTimings {
+        scan_ms: scan_time.as_millis() as u64,
+        copy_ms: copy_time.as_millis() as u64,
+        rewrite_ms: rewrite_started.elapsed().as_millis() as u64,
+    };
+    log::info!(
+        "[copy_html_with_images] {} {} assets alongside '{}' (scan {}ms, copy {}ms, rewrite {}ms)",
+        if dry_run { "Would copy" } else { "Copied" },
+        copied_assets.len(),
+        filename,
+        timings.scan_ms,
+        timings.copy_ms,
+        timings.rewrite_ms
+    );
+    Ok(CopyHtmlResult { filename, copied_assets, timings, dry_run })
+}
+
+/// An HTML document being imported and the attribute rewrites it needs
+struct HtmlDocument {
+    /// Where the document is copied, relative to the data dir
+    dest: PathBuf,
+    /// UTF-8 content with charset declarations saying so
+    content: String,
+    /// `content` differs from the source bytes (transcoded or charset redeclared)
+    transcoded: bool,
+    edits: Vec<AssetPathEdit>,
+}
+
+/// Replace the attribute value at `range` once `asset` has been copied
+struct AssetPathEdit {
+    range: std::ops::Range<usize>,
+    asset: PathBuf,
+    replacement: String,
+}
+
+/// Collect the existing local assets one HTML document references, as normalized
+/// paths relative to `source_root`. `html_dir` is the document's own directory
+/// relative to `source_root`. Assets are copied to `asset_root` joined with that
+/// path, and `dest` is where this document lands, both relative to the data dir;
+/// `visited` holds the canonical paths of every document already scanned.
+/// References whose written form differs from the path from the document's
+/// destination to the asset's (`./a/../a/b.png`) get an edit on the document.
+#[allow(clippy::too_many_arguments)]
+fn collect_html_assets(
+    source_root: &Path,
+    html_dir: &Path,
+    asset_root: &Path,
+    dest: PathBuf,
+    content: String,
+    transcoded: bool,
+    follow_iframes: bool,
+    visited: &mut std::collections::HashSet<PathBuf>,
+    assets: &mut Vec<PathBuf>,
+    documents: &mut Vec<HtmlDocument>,
+) {
+    let tags = scan_html_tags(&content);
+    // Relative URLs resolve against <base href>, which is copied verbatim with the HTML
+    let base_dir = html_base_dir(&tags);
+    let resolve_dir = html_dir.join(&base_dir);
+    // Where the copied document's references resolve from, relative to the data dir
+    let dest_dir = normalize_relative_path(&dest.parent().unwrap_or(Path::new("")).join(&base_dir));
+    let frames = if follow_iframes { local_iframe_refs(&tags) } else { Vec::new() };
+    let refs = local_asset_refs(&tags)
+        .into_iter()
+        .map(|value| (value, false))
+        .chain(frames.into_iter().map(|attr| (attr.value, true)));
+
+    let mut edits = Vec::new();
+    for (value, is_frame) in refs {
+        // Attribute values are HTML-escaped first, URL-encoded second
+        let decoded = urlencoding_decode(&decode_html_entities(value.trim()));
+        // References climbing out of the export folder would land outside the data dir
+        let Some(relative) = normalize_relative_path(&resolve_dir.join(&decoded)) else {
+            continue;
+        };
+        let asset_source = source_root.join(&relative);
+        if !asset_source.is_file() {
+            continue;
+        }
+        let asset_dest = asset_root.join(sanitize_relative_path(&relative));
+        if let Some(dir) = &dest_dir {
+            let canonical = relative_path_between(dir, &asset_dest);
+            if canonical != decoded {
+                let start = value.as_ptr() as usize - content.as_ptr() as usize;
+                let encoded: Vec<String> = canonical.split('/').map(urlencoding_encode).collect();
+                edits.push(AssetPathEdit {
+                    range: start..start + value.len(),
+                    asset: relative.clone(),
+                    replacement: encoded.join("/"),
+                });
+            }
+        }
+        if assets.contains(&relative) {
+            continue;
+        }
+        if is_frame {
+            let key = fs::canonicalize(&asset_source).unwrap_or_else(|_| asset_source.clone());
+            if !visited.insert(key) {
+                continue;
+            }
+        }
+        assets.push(relative.clone());
+        if is_frame {
+            let (nested, nested_transcoded) = read_html_as_utf8(&asset_source).unwrap_or_default();
+            let nested_dir = relative.parent().map(Path::to_path_buf).unwrap_or_default();
+            collect_html_assets(
+                source_root,
+                &nested_dir,
+                asset_root,
+                asset_dest,
+                nested,
+                nested_transcoded,
+                follow_iframes,
+                visited,
+                assets,
+                documents,
+            );
+        }
+    }
+    drop(tags);
+    documents.push(HtmlDocument { dest, content, transcoded, edits });
+}
+
+/// Read an HTML file as UTF-8 with its `<meta>` charset declarations rewritten
+/// to say so. Files that aren't valid UTF-8 are decoded with their declared
+/// charset, or a guess when there is none. The flag is set when the result
+/// differs from the file's bytes.
+fn read_html_as_utf8(path: &Path) -> std::io::Result<(String, bool)> {
+    let (content, transcoded) = match String::from_utf8(fs::read(path)?) {
+        Ok(content) => (content, false),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            let declared = html_charset_ranges(&String::from_utf8_lossy(&bytes))
+                .into_iter()
+                .next()
+                .and_then(|(_, label)| encoding_rs::Encoding::for_label(label.as_bytes()))
+                .filter(|encoding| *encoding != encoding_rs::UTF_8);
+            let encoding = declared.unwrap_or_else(|| guess_legacy_encoding(&bytes));
+            (encoding.decode_without_bom_handling(&bytes).0.into_owned(), true)
+        }
+    };
+    let mut ranges: Vec<_> = html_charset_ranges(&content)
+        .into_iter()
+        .filter(|(_, label)| !label.eq_ignore_ascii_case("utf-8"))
+        .map(|(range, _)| range)
+        .collect();
+    if ranges.is_empty() {
+        return Ok((content, transcoded));
+    }
+    ranges.sort_by_key(|range| std::cmp::Reverse(range.start));
+    let mut content = content;
+    for range in ranges {
+        content.replace_range(range, "UTF-8");
+    }
+    Ok((content, true))
+}
+
+/// Byte ranges and values of the charset in `<meta charset>` and in
+/// `<meta http-equiv="Content-Type" content="text/html; charset=...">`
+fn html_charset_ranges(content: &str) -> Vec<(std::ops::Range<usize>, String)> {
+    let mut ranges = Vec::new();
+    for tag in scan_html_tags(content).iter().filter(|t| t.name == "meta") {
+        let value = if let Some(charset) = tag.attr("charset") {
+            let trimmed = charset.value.trim();
+            let start = trimmed.as_ptr() as usize - content.as_ptr() as usize;
+            Some((start, trimmed))
+        } else if tag
+            .attr("http-equiv")
+            .is_some_and(|a| a.value.trim().eq_ignore_ascii_case("content-type"))
+        {
+            tag.attr("content").and_then(|attr| {
+                let at = find_ignore_ascii_case(attr.value, 0, "charset=")? + "charset=".len();
+                let rest = &attr.value[at..];
+                let len = rest.find(|c: char| c == ';' || c == '"' || c == '\'' || c.is_whitespace()).unwrap_or(rest.len());
+                let start = rest.as_ptr() as usize - content.as_ptr() as usize;
+                Some((start, &rest[..len]))
+            })
+        } else {
+            None
+        };
+        if let Some((start, label)) = value.filter(|(_, label)| !label.is_empty()) {
+            ranges.push((start..start + label.len(), label.to_string()));
+        }
+    }
+    ranges
+}
+
+/// Resolve `.` and `..` lexically; `None` if the path climbs above its start
+fn normalize_relative_path(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Forward-slash path from directory `from` to `to`, both normalized relative paths
+fn relative_path_between(from: &Path, to: &Path) -> String {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(to[common..].iter().map(|c| c.as_os_str().to_string_lossy().to_string()));
+    parts.join("/")
+}
+
+struct HtmlAttr<'a> {
+    /// Lowercased attribute name
+    name: String,
+    value: &'a str,
+}
+
+struct HtmlTag<'a> {
+    /// Lowercased tag name
+    name: String,
+    attrs: Vec<HtmlAttr<'a>>,
+}
+
+impl<'a> HtmlTag<'a> {
+    fn attr(&self, name: &str) -> Option<&HtmlAttr<'a>> {
+        self.attrs.iter().find(|a| a.name == name)
+    }
+}
+
+/// Minimal start-tag scanner. Skips comments, end tags and the bodies of
+/// `<script>`/`<style>`, and handles quoted, unquoted and valueless attributes.
+fn scan_html_tags(content: &str) -> Vec<HtmlTag<'_>> {
+    let bytes = content.as_bytes();
+    let len = bytes.len();
+    let mut tags = Vec::new();
+    let mut pos = 0;
+
+    while let Some(rel) = content[pos..].find('<') {
+        let start = pos + rel;
+        pos = start + 1;
+        if content[start..].starts_with("<!--") {
+            pos = content[start + 4..]
+                .find("-->")
+                .map(|i| start + 4 + i + 3)
+                .unwrap_or(len);
+            continue;
+        }
+        if pos >= len || !bytes[pos].is_ascii_alphabetic() {
+            continue;
+        }
+        let name_len = bytes[pos..].iter().take_while(|b| b.is_ascii_alphanumeric() || **b == b'-').count();
+        let name = content[pos..pos + name_len].to_ascii_lowercase();
+
+        let mut i = pos + name_len;
+        let mut attrs = Vec::new();
+        loop {
+            while i < len && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+                i += 1;
+            }
+            if i >= len || bytes[i] == b'>' {
+                break;
+            }
+            let name_start = i;
+            while i < len && !bytes[i].is_ascii_whitespace() && !matches!(bytes[i], b'=' | b'>' | b'/') {
+                i += 1;
+            }
+            let attr_name = content[name_start..i].to_ascii_lowercase();
+            while i < len && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i < len && bytes[i] == b'=' {
+                i += 1;
+                while i < len && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                let (value_start, value_end) = if i < len && (bytes[i] == b'"' || bytes[i] == b'\'') {
+                    let quote = bytes[i] as char;
+                    let value_start = i + 1;
+                    let value_end = content[value_start..]
+                        .find(quote)
+                        .map(|k| value_start + k)
+                        .unwrap_or(len);
+                    i = (value_end + 1).min(len);
+                    (value_start, value_end)
+                } else {
+                    let value_start = i;
+                    while i < len && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                        i += 1;
+                    }
+                    (value_start, i)
+                };
+                if !attr_name.is_empty() {
+                    attrs.push(HtmlAttr { name: attr_name, value: &content[value_start..value_end] });
+                }
+            } else if !attr_name.is_empty() {
+                attrs.push(HtmlAttr { name: attr_name, value: "" });
+            } else {
+                // Stray '=' with no name; step over it
+                i += 1;
+            }
+        }
+        pos = i.min(len);
+
+        let raw_text = name == "script" || name == "style";
+        if raw_text {
+            let close = format!("</{}", name);
+            pos = find_ignore_ascii_case(content, pos, &close).unwrap_or(len);
+        }
+        tags.push(HtmlTag { name, attrs });
+    }
+    tags
+}
+
+fn find_ignore_ascii_case(haystack: &str, from: usize, needle: &str) -> Option<usize> {
+    let needle = needle.as_bytes();
+    haystack.as_bytes()[from..]
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+        .map(|i| from + i)
+}
+
+/// Relative references to files next to the HTML (not URLs, data/blob URIs or absolute paths)
+fn is_local_asset_ref(value: &str) -> bool {
+    let trimmed = value.trim();
+    !trimmed.is_empty()
+        && !trimmed.starts_with("http://")
+        && !trimmed.starts_with("https://")
+        && !trimmed.starts_with("data:")
+        && !trimmed.starts_with("blob:")
+        && !trimmed.starts_with("file:")
+        && !trimmed.starts_with('/')
+}
+
+/// Directory from the first `<base href>`, relative to the HTML file. Empty when
+/// there is no base or it is an absolute URL, so assets resolve next to the HTML.
+fn html_base_dir(tags: &[HtmlTag<'_>]) -> PathBuf {
+    let href = tags
+        .iter()
+        .find(|t| t.name == "base")
+        .and_then(|t| t.attr("href"))
+        .map(|a| a.value.trim())
+        .unwrap_or("");
+    if !is_local_asset_ref(href) || href.contains(':') {
+        return PathBuf::new();
+    }
+    let decoded = urlencoding_decode(&decode_html_entities(href));
+    // "images/index.html" means the "images/" directory
+    match decoded.rfind('/') {
+        Some(slash) => PathBuf::from(&decoded[..slash]),
+        None => PathBuf::new(),
+    }
+}
+
+/// Local asset paths referenced by `<img src>`, lazy-loading `<img data-src>`,
+/// the fallback image in `<canvas data-src>`, favicon `<link href>`s and every
+/// candidate of an `srcset` on `<img>` or a `<picture>`'s `<source>`s. Each path is a slice of the attribute value.
+fn local_asset_refs<'a>(tags: &[HtmlTag<'a>]) -> Vec<&'a str> {
+    let mut refs = Vec::new();
+    for tag in tags {
+        let (attrs, srcset) = match tag.name.as_str() {
+            "img" => ([tag.attr("src"), tag.attr("data-src")], tag.attr("srcset")),
+            "canvas" => ([tag.attr("data-src"), None], None),
+            "source" => ([None, None], tag.attr("srcset")),
+            "link" => {
+                let rel = tag.attr("rel").map(|a| a.value.to_ascii_lowercase()).unwrap_or_default();
+                let is_icon = rel
+                    .split_ascii_whitespace()
+                    .any(|t| t == "icon" || t.starts_with("apple-touch-icon"));
+                ([if is_icon { tag.attr("href") } else { None }, None], None)
+            }
+            _ => continue,
+        };
+        let values = attrs
+            .into_iter()
+            .flatten()
+            .map(|a| a.value)
+            .chain(srcset.into_iter().flat_map(|a| srcset_urls(a.value)));
+        refs.extend(values.filter(|v| is_local_asset_ref(v)));
+    }
+    refs
+}
+
+/// URLs of a `srcset` value (`a.png 1x, b.png 2x`), as slices of it. A URL
+/// runs to the next whitespace; trailing commas on it end the candidate, so
+/// commas inside URLs are kept.
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let bytes = srcset.as_bytes();
+    let mut urls = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b',') {
+            i += 1;
+        }
+        let start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let url = srcset[start..i].trim_end_matches(',');
+        if url.is_empty() {
+            continue;
+        }
+        urls.push(url);
+        if url.len() == i - start {
+            // Skip the descriptors ("2x", "480w") up to the next candidate
+            while i < bytes.len() && bytes[i] != b',' {
+                i += 1;
+            }
+        }
+    }
+    urls
+}
+
+/// `<iframe src>` attributes pointing at local HTML documents
+fn local_iframe_refs<'t, 'a>(tags: &'t [HtmlTag<'a>]) -> Vec<&'t HtmlAttr<'a>> {
+    tags.iter()
+        .filter(|t| t.name == "iframe")
+        .filter_map(|t| t.attr("src"))
+        .filter(|a| is_local_asset_ref(a.value))
+        .filter(|a| {
+            let path = a.value.trim().to_ascii_lowercase();
+            path.ends_with(".html") || path.ends_with(".htm")
+        })
+        .collect()
+}
+
+#[derive(serde::Serialize)]
+struct BrokenAssetLink {
+    /// The reference as written in the HTML
+    reference: String,
+    /// Data-dir path it resolves to; `None` when it climbs out of the data dir
+    resolved: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct PaperAssetLinks {
+    paper: String,
+    broken: Vec<BrokenAssetLink>,
+}
+
+#[derive(serde::Serialize)]
+struct AssetLinkReport {
+    papers_checked: usize,
+    links_checked: usize,
+    /// Only papers with at least one broken reference, sorted by path
+    papers: Vec<PaperAssetLinks>,
+}
+
+/// Check that every local reference in every HTML paper still points at a file
+/// in the data dir, e.g. after moving the data dir or renaming folders. Covers
+/// the assets `copy_html_with_images` copies plus `href`s, `src`s of scripts,
+/// frames and media, and `url()`s in `style` attributes and `<style>` blocks.
+/// Query strings and fragments are ignored; `#anchor` links are skipped.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn verify_all_asset_links(app: tauri::AppHandle) -> Result<AssetLinkReport, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || verify_all_asset_links_in(&data_dir)).await
+}
+
+fn verify_all_asset_links_in(data_dir: &Path) -> Result<AssetLinkReport, String> {
+    use rayon::prelude::*;
+
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    let html_files: Vec<&PathBuf> = files
+        .iter()
+        .filter(|path| {
+            let ext = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+            matches!(ext.as_deref(), Some("html" | "htm"))
+        })
+        .collect();
+    let checked: Vec<(usize, PaperAssetLinks)> = html_files
+        .par_iter()
+        .filter_map(|path| {
+            let paper = relative_data_path(data_dir, path).ok()?;
+            let content = match read_html_as_utf8(path) {
+                Ok((content, _)) => content,
+                Err(e) => {
+                    log::warn!("[verify_all_asset_links] Skipping '{}': {}", paper, e);
+                    return None;
+                }
+            };
+            let tags = scan_html_tags(&content);
+            let html_dir = Path::new(&paper).parent().unwrap_or(Path::new("")).join(html_base_dir(&tags));
+            let refs = html_link_refs(&content, &tags);
+            let broken = refs
+                .iter()
+                .filter_map(|reference| {
+                    let target = reference.split(['?', '#']).next().unwrap_or("");
+                    let decoded = urlencoding_decode(&decode_html_entities(target));
+                    match normalize_relative_path(&html_dir.join(&decoded)) {
+                        Some(relative) if data_dir.join(&relative).exists() => None,
+                        resolved => Some(BrokenAssetLink {
+                            reference: reference.to_string(),
+                            resolved: resolved.map(|p| p.to_string_lossy().replace('\\', "/")),
+                        }),
+                    }
+                })
+                .collect();
+            Some((refs.len(), PaperAssetLinks { paper, broken }))
+        })
+        .collect();
+
+    let papers_checked = checked.len();
+    let links_checked = checked.iter().map(|(links, _)| links).sum();
+    let mut papers: Vec<PaperAssetLinks> =
+        checked.into_iter().map(|(_, paper)| paper).filter(|p| !p.broken.is_empty()).collect();
+    papers.sort_by(|a, b| a.paper.cmp(&b.paper));
+    Ok(AssetLinkReport { papers_checked, links_checked, papers })
+}
+
+/// Distinct local references in a document: `local_asset_refs`, then each tag's
+/// `href`/`src`/`poster`/`data` and `style` `url()`s, then `<style>` block `url()`s
+fn html_link_refs<'a>(content: &'a str, tags: &[HtmlTag<'a>]) -> Vec<&'a str> {
+    let mut values = local_asset_refs(tags);
+    for tag in tags {
+        let attrs: &[&str] = match tag.name.as_str() {
+            "a" | "area" | "link" => &["href"],
+            "script" | "iframe" | "frame" | "embed" | "audio" | "track" => &["src"],
+            "video" => &["src", "poster"],
+            "source" => &["src"],
+            "object" => &["data"],
+            _ => &[],
+        };
+        values.extend(attrs.iter().filter_map(|name| tag.attr(name)).map(|a| a.value));
+        if let Some(style) = tag.attr("style") {
+            values.extend(css_urls(style.value));
+        }
+    }
+    let mut pos = 0;
+    while let Some(open) = find_ignore_ascii_case(content, pos, "<style") {
+        let body_start = content[open..].find('>').map_or(content.len(), |i| open + i + 1);
+        let body_end = find_ignore_ascii_case(content, body_start, "</style").unwrap_or(content.len());
+        values.extend(css_urls(&content[body_start..body_end]));
+        pos = body_end;
+    }
+
+    let mut refs: Vec<&str> = Vec::new();
+    for value in values.into_iter().map(str::trim) {
+        // Skip in-page anchors and other schemes (mailto:, javascript:, ...)
+        let is_scheme = value.find(':').is_some_and(|colon| !value[..colon].contains('/'));
+        if is_local_asset_ref(value) && !value.starts_with('#') && !is_scheme && !refs.contains(&value) {
+            refs.push(value);
+        }
+    }
+    refs
+}
+
+/// Targets of the `url(...)` functions in CSS, quoted or not, as slices of it
+fn css_urls(css: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = find_ignore_ascii_case(css, pos, "url(") {
+        let mut begin = start + 4;
+        begin += css[begin..].len() - css[begin..].trim_start().len();
+        let (quote, begin) = match css[begin..].chars().next() {
+            Some(q @ ('"' | '\'')) => (Some(q), begin + 1),
+            _ => (None, begin),
+        };
+        let end = match quote {
+            Some(q) => css[begin..].find(q),
+            None => css[begin..].find(')'),
+        }
+        .map_or(css.len(), |i| begin + i);
+        urls.push(css[begin..end].trim());
+        pos = end;
+    }
+    urls
+}
+
+/// Decode the named entities common in attribute values plus numeric
+/// (`&#123;`, `&#x7B;`) references. Unknown entities are left as written.
+fn decode_html_entities(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&end| end <= 12).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => {
+                    let code = if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        u32::from_str_radix(hex, 16).ok()
+                    } else if let Some(dec) = entity.strip_prefix('#') {
+                        dec.parse::<u32>().ok()
+                    } else {
+                        None
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Simple URL decoding (handles %XX sequences).
+/// Decodes to raw bytes first so multi-byte UTF-8 sequences like `%ED%95%9C` survive.
+fn urlencoding_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                decoded.push((hi * 16 + lo) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters
+/// (like JS `encodeURIComponent`, but `!*'()` are escaped too)
+fn urlencoding_encode(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    for &b in input.as_bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+    result
+}
+
+#[derive(serde::Serialize)]
+struct NoteHeading {
+    level: u8,
+    text: String,
+    slug: String,
+}
+
+/// Extract markdown (`#`..`######`) and HTML (`<h1>`..`<h6>`) headings in document order
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn extract_note_headings(app: tauri::AppHandle, filename: String) -> Result<Vec<NoteHeading>, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let content = read_data_file_in(&root, &relative)?;
+
+    let lower_name = filename.to_lowercase();
+    let is_html = lower_name.ends_with(".html") || lower_name.ends_with(".htm");
+
+    // Collect (byte offset, level, text) from both syntaxes, then merge by offset
+    let mut found: Vec<(usize, u8, String)> = Vec::new();
+    if !is_html {
+        found.extend(markdown_headings(&content));
+    }
+    found.extend(html_headings(&content));
+    found.sort_by_key(|(offset, _, _)| *offset);
+
+    let mut used_slugs: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
+    let headings = found
+        .into_iter()
+        .filter(|(_, _, text)| !text.is_empty())
+        .map(|(_, level, text)| {
+            let base = heading_slug(&text);
+            let count = used_slugs.entry(base.clone()).or_insert(0);
+            let slug = if *count == 0 { base } else { format!("{}-{}", base, count) };
+            *count += 1;
+            NoteHeading { level, text, slug }
+        })
+        .collect();
+    Ok(headings)
+}
+
+/// ATX-style markdown headings, skipping fenced code blocks
+fn markdown_headings(content: &str) -> Vec<(usize, u8, String)> {
+    let mut headings = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        let indent = trimmed.len() - trimmed.trim_start_matches(' ').len();
+        if indent > 3 {
+            continue;
+        }
+        let body = &trimmed[indent..];
+
+        if let Some(marker) = fence {
+            if body.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if body.starts_with("```") {
+            fence = Some("```");
+            continue;
+        }
+        if body.starts_with("~~~") {
+            fence = Some("~~~");
+            continue;
+        }
+
+        let level = body.bytes().take_while(|&b| b == b'#').count();
+        if level == 0 || level > 6 {
+            continue;
+        }
+        let rest = &body[level..];
+        if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+            continue;
+        }
+        // Drop an optional closing sequence of #s ("## Title ##")
+        let mut text = rest.trim();
+        let without_closing = text.trim_end_matches('#');
+        if without_closing.is_empty() || without_closing.ends_with([' ', '\t']) {
+            text = without_closing.trim_end();
+        }
+        headings.push((line_start, level as u8, text.to_string()));
+    }
+    headings
+}
+
+/// `<h1>`..`<h6>` elements, with inner tags stripped
+fn html_headings(content: &str) -> Vec<(usize, u8, String)> {
+    let mut headings = Vec::new();
+    let lower = content.to_ascii_lowercase();
+    let mut pos = 0;
+    while let Some(rel) = lower[pos..].find("<h") {
+        let start = pos + rel;
+        pos = start + 2;
+        let level = match lower.as_bytes().get(start + 2) {
+            Some(b) if (b'1'..=b'6').contains(b) => b - b'0',
+            _ => continue,
+        };
+        // Must be followed by '>' or whitespace/attributes, not e.g. "<h1x"
+        match lower.as_bytes().get(start + 3) {
+            Some(b'>') | Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') => {}
+            _ => continue,
+        }
+        let open_end = match lower[start..].find('>') {
+            Some(i) => start + i + 1,
+            None => break,
+        };
+        let close_tag = format!("</h{}", level);
+        let close = match lower[open_end..].find(&close_tag) {
+            Some(i) => open_end + i,
+            None => continue,
+        };
+        let text = strip_html_tags(&content[open_end..close]);
+        headings.push((start, level, text));
+        pos = close;
+    }
+    headings
+}
+
+#[derive(serde::Serialize)]
+struct FigureCaption {
+    src: String,
+    /// Empty when the figure has no `<figcaption>`
+    caption: String,
+}
+
+/// Images inside `<figure>` elements paired with their `<figcaption>` text, in document order
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn extract_figure_captions(app: tauri::AppHandle, filename: String) -> Result<Vec<FigureCaption>, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let content = read_data_file_in(&root, &relative)?;
+
+    let mut figures = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = find_ignore_ascii_case(&content, pos, "<figure") {
+        let end = find_ignore_ascii_case(&content, start, "</figure")
+            .unwrap_or(content.len());
+        let block = &content[start..end];
+        pos = end.max(start + 1);
+
+        let caption = find_ignore_ascii_case(block, 0, "<figcaption")
+            .and_then(|open| {
+                let inner_start = open + block[open..].find('>')? + 1;
+                let inner_end = find_ignore_ascii_case(block, inner_start, "</figcaption")
+                    .unwrap_or(block.len());
+                Some(strip_html_tags(&block[inner_start..inner_end]))
+            })
+            .unwrap_or_default();
+
+        for tag in scan_html_tags(block).iter().filter(|t| t.name == "img") {
+            if let Some(src) = tag.attr("src").filter(|a| !a.value.trim().is_empty()) {
+                figures.push(FigureCaption {
+                    src: src.value.trim().to_string(),
+                    caption: caption.clone(),
+                });
+            }
+        }
+    }
+    Ok(figures)
+}
+
+#[derive(serde::Serialize)]
+struct FootnoteLocation {
+    /// 1-based
+    line: usize,
+    /// 1-based, in characters
+    column: usize,
+}
+
+#[derive(serde::Serialize)]
+struct Footnote {
+    id: String,
+    references: Vec<FootnoteLocation>,
+    /// `None` when the footnote is never defined
+    definition: Option<String>,
+    /// Referenced but never defined
+    orphaned: bool,
+    /// Defined but never referenced
+    unused: bool,
+}
+
+/// Footnotes in order of first appearance: markdown `[^id]` references and
+/// `[^id]: text` definitions (indented lines continue a definition), and in
+/// HTML the `footnote-ref`/`doc-noteref` links and the elements they point to.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn extract_footnotes(app: tauri::AppHandle, filename: String) -> Result<Vec<Footnote>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    extract_footnotes_in(&data_dir, &filename)
+}
+
+fn extract_footnotes_in(data_dir: &Path, filename: &str) -> Result<Vec<Footnote>, String> {
+    resolve_data_path(data_dir, filename)?;
+    let content = read_data_file_in(data_dir, filename)?;
+    let lower_name = filename.to_lowercase();
+    let is_html = lower_name.ends_with(".html") || lower_name.ends_with(".htm");
+
+    // (byte offset, id) for references and (byte offset, id, text) for definitions
+    let (mut refs, mut defs) = if is_html {
+        html_footnotes(&content)
+    } else {
+        markdown_footnotes(&content)
+    };
+    refs.sort_by_key(|(offset, _)| *offset);
+    defs.sort_by_key(|(offset, _, _)| *offset);
+
+    let mut order: Vec<(usize, &str)> = Vec::new();
+    for (offset, id) in refs.iter().map(|(o, id)| (*o, id)).chain(defs.iter().map(|(o, id, _)| (*o, id))) {
+        match order.iter_mut().find(|(_, seen)| *seen == id.as_str()) {
+            Some(entry) => entry.0 = entry.0.min(offset),
+            None => order.push((offset, id)),
+        }
+    }
+    order.sort_by_key(|(offset, _)| *offset);
+
+    let footnotes = order
+        .into_iter()
+        .map(|(_, id)| {
+            let references: Vec<FootnoteLocation> = refs
+                .iter()
+                .filter(|(_, r)| r == id)
+                .map(|(offset, _)| {
+                    let (line, column) = line_column(&content, *offset);
+                    FootnoteLocation { line, column }
+                })
+                .collect();
+            // A repeated definition is ignored, as markdown renderers do
+            let definition = defs.iter().find(|(_, d, _)| d == id).map(|(_, _, text)| text.clone());
+            Footnote {
+                id: id.to_string(),
+                orphaned: definition.is_none(),
+                unused: references.is_empty(),
+                references,
+                definition,
+            }
+        })
+        .collect();
+    Ok(footnotes)
+}
+
+/// 1-based line and character column of a byte offset
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+#[allow(clippy::type_complexity)]
+fn markdown_footnotes(content: &str) -> (Vec<(usize, String)>, Vec<(usize, String, String)>) {
+    let mut refs = Vec::new();
+    let mut defs: Vec<(usize, String, String)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    // Index into `defs` of the definition that indented lines continue
+    let mut open_def: Option<usize> = None;
+    let mut after_blank = false;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        let indent = trimmed.len() - trimmed.trim_start_matches(' ').len();
+        let body = trimmed.trim_start_matches(' ');
+
+        if let Some(marker) = fence {
+            if indent <= 3 && body.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if body.is_empty() {
+            after_blank = true;
+            continue;
+        }
+
+        let continues = indent >= 4 || trimmed.starts_with('\t');
+        if let (Some(i), true) = (open_def, continues) {
+            let text = &mut defs[i].2;
+            text.push_str(if after_blank { "\n\n" } else { " " });
+            text.push_str(body.trim());
+            after_blank = false;
+            markdown_footnote_refs(body, line_start + (trimmed.len() - body.len()), &mut refs);
+            continue;
+        }
+        open_def = None;
+        after_blank = false;
+        if indent > 3 {
+            continue;
+        }
+        if body.starts_with("```") || body.starts_with("~~~") {
+            fence = Some(&body[..3]);
+            continue;
+        }
+
+        let body_start = line_start + indent;
+        if let Some((id, label_end)) = markdown_footnote_label(body).filter(|(_, end)| body[*end..].starts_with(':')) {
+            let text = &body[label_end + 1..];
+            defs.push((body_start, id.to_string(), text.trim().to_string()));
+            open_def = Some(defs.len() - 1);
+            markdown_footnote_refs(text, body_start + label_end + 1, &mut refs);
+        } else {
+            markdown_footnote_refs(body, body_start, &mut refs);
+        }
+    }
+    (refs, defs)
+}
+
+/// `[^id]` at the start of `text`: the id and the offset just past `]`
+fn markdown_footnote_label(text: &str) -> Option<(&str, usize)> {
+    let rest = text.strip_prefix("[^")?;
+    let close = rest.find(']')?;
+    let id = &rest[..close];
+    if id.is_empty() || id.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((id, close + 3))
+}
+
+/// `[^id]` references in `text`, skipping inline code spans
+fn markdown_footnote_refs(text: &str, base: usize, refs: &mut Vec<(usize, String)>) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'`' {
+            let run = bytes[i..].iter().take_while(|&&b| b == b'`').count();
+            let marker = &text[i..i + run];
+            // An unclosed run is literal text, as in CommonMark
+            i = text[i + run..]
+                .find(marker)
+                .map(|end| i + run + end + run)
+                .unwrap_or(i + run);
+            continue;
+        }
+        if let Some((id, end)) = markdown_footnote_label(&text[i..]) {
+            refs.push((base + i, id.to_string()));
+            i += end;
+            continue;
+        }
+        i += 1;
+    }
+}
+
+/// Footnote links (`class="footnote-ref"`, `role="doc-noteref"`, `rel="footnote"`)
+/// and the elements they point to, as produced by pandoc, markdown-it and the like
+#[allow(clippy::type_complexity)]
+fn html_footnotes(content: &str) -> (Vec<(usize, String)>, Vec<(usize, String, String)>) {
+    let value_offset = |value: &str| value.as_ptr() as usize - content.as_ptr() as usize;
+    let tags = scan_html_tags(content);
+
+    let mut refs = Vec::new();
+    for tag in tags.iter().filter(|t| t.name == "a" || t.name == "sup") {
+        let has = |name: &str, needle: &str| {
+            tag.attr(name)
+                .map(|a| a.value.split_whitespace().any(|v| v.eq_ignore_ascii_case(needle)))
+                .unwrap_or(false)
+        };
+        let marked = has("class", "footnote-ref") || has("role", "doc-noteref") || has("rel", "footnote");
+        if !marked {
+            continue;
+        }
+        // markdown-it puts the class on the <sup> wrapping the link
+        let link = if tag.name == "a" {
+            Some(tag)
+        } else {
+            let after = value_offset(tag.attrs[0].value);
+            tags.iter()
+                .find(|t| t.name == "a" && t.attrs.first().map(|a| value_offset(a.value) > after).unwrap_or(false))
+        };
+        let Some(href) = link.and_then(|l| l.attr("href")) else {
+            continue;
+        };
+        if let Some(id) = href.value.trim().strip_prefix('#').filter(|id| !id.is_empty()) {
+            if !refs.iter().any(|(offset, _)| *offset == value_offset(href.value)) {
+                refs.push((value_offset(href.value), id.to_string()));
+            }
+        }
+    }
+
+    let mut defs = Vec::new();
+    for tag in &tags {
+        let Some(id) = tag.attr("id").map(|a| a.value.trim()) else {
+            continue;
+        };
+        let is_note = tag
+            .attr("role")
+            .map(|a| a.value.eq_ignore_ascii_case("doc-endnote") || a.value.eq_ignore_ascii_case("doc-footnote"))
+            .unwrap_or(false)
+            || tag.attr("class").map(|a| a.value.split_whitespace().any(|c| c == "footnote-item")).unwrap_or(false)
+            || refs.iter().any(|(_, r)| r == id);
+        if !is_note || defs.iter().any(|(_, d, _): &(usize, String, String)| d == id) {
+            continue;
+        }
+        let tag_start = content[..value_offset(tag.attrs[0].value)].rfind('<').unwrap_or(0);
+        let Some(inner_start) = content[tag_start..].find('>').map(|i| tag_start + i + 1) else {
+            continue;
+        };
+        let inner_end = find_ignore_ascii_case(content, inner_start, &format!("</{}", tag.name))
+            .unwrap_or(content.len());
+        // Drop the "back to text" arrows pandoc and markdown-it append
+        let text = strip_html_tags(&content[inner_start..inner_end]).replace(['\u{21a9}', '\u{fe0e}'], "");
+        defs.push((tag_start, id.to_string(), text.trim().to_string()));
+    }
+    (refs, defs)
+}
+
+/// Remove tags and collapse whitespace from an HTML fragment
+fn strip_html_tags(fragment: &str) -> String {
+    let mut text = String::with_capacity(fragment.len());
+    let mut in_tag = false;
+    for c in fragment.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// GitHub-style anchor slug: lowercase, spaces to '-', punctuation dropped
+fn heading_slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                Some(c)
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// File in the data dir recording which migrations have been applied
+const DATA_VERSION_FILE: &str = "data_version";
+
+type MigrationStep = fn(&Path) -> Result<(), String>;
+
+/// Ordered on-disk schema migrations. Each step must be idempotent so an
+/// interrupted run can simply be repeated.
+const MIGRATIONS: &[(u32, &str, MigrationStep)] = &[
+    (1, "create helper folders", migrate_v1_helper_folders),
+];
+
+#[derive(serde::Serialize)]
+struct MigrationReport {
+    from_version: u32,
+    to_version: u32,
+    applied: Vec<String>,
+}
+
+fn read_data_version(data_dir: &Path) -> Result<u32, String> {
+    let path = data_dir.join(DATA_VERSION_FILE);
+    if !path.exists() {
+        // Data dirs created before versioning was introduced
+        return Ok(0);
+    }
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read data version: {}", e))?;
+    raw.trim()
+        .parse::<u32>()
+        .map_err(|e| format!("Invalid data version '{}': {}", raw.trim(), e))
+}
+
+fn run_migrations(data_dir: &Path) -> Result<MigrationReport, String> {
+    let from_version = read_data_version(data_dir)?;
+    let latest = MIGRATIONS.last().map(|(v, _, _)| *v).unwrap_or(0);
+    if from_version > latest {
+        return Err(format!(
+            "Data version {} is newer than this app supports ({})",
+            from_version, latest
+        ));
+    }
+
+    let mut applied = Vec::new();
+    let mut current = from_version;
+    for (version, name, step) in MIGRATIONS.iter().filter(|(v, _, _)| *v > from_version) {
+        log::info!("[migrate_data] Applying migration {} ({})", version, name);
+        step(data_dir).map_err(|e| format!("Migration {} ({}) failed: {}", version, name, e))?;
+        // Record progress after every step so a crash resumes from here
+        atomic_write(&data_dir.join(DATA_VERSION_FILE), version.to_string().as_bytes())
+            .map_err(|e| format!("Failed to write data version: {}", e))?;
+        current = *version;
+        applied.push(name.to_string());
+    }
+    if applied.is_empty() {
+        log::info!("[migrate_data] Data version {} is up to date", current);
+    }
+    Ok(MigrationReport { from_version, to_version: current, applied })
+}
+
+/// v1: folders the front-end stores annotations and caches in
+fn migrate_v1_helper_folders(data_dir: &Path) -> Result<(), String> {
+    for folder in ["annotations", "cache"] {
+        fs::create_dir_all(data_dir.join(folder))
+            .map_err(|e| format!("Failed to create '{}' folder: {}", folder, e))?;
+    }
+    Ok(())
+}
+
+/// Bring the data directory up to the latest schema version
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn migrate_data(app: tauri::AppHandle) -> Result<MigrationReport, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_migrations(&data_dir)
+}
+
+/// Hex SHA-256 of a file, streamed rather than read into memory
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    let mut file = fs::File::open(path)?;
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex_encode(&hasher.finalize()))
+}
+
+#[derive(serde::Serialize)]
+struct HashTreeNode {
+    /// Data-dir relative path; empty for the root
+    path: String,
+    is_dir: bool,
+    /// Content hash for files; hash of the sorted children's names and hashes for folders
+    hash: String,
+    children: Vec<HashTreeNode>,
+}
+
+/// Merkle-style hash tree of the library. Sync clients compare root hashes and only
+/// descend into folders whose hashes differ. Helper folders and caches are excluded.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn compute_data_tree_hashes(app: tauri::AppHandle) -> Result<HashTreeNode, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || hash_tree_node(&data_dir, &data_dir)).await
+}
+
+fn hash_tree_node(data_dir: &Path, path: &Path) -> Result<HashTreeNode, String> {
+    use sha2::Digest;
+
+    let rel = relative_data_path(data_dir, path)?;
+    if !path.is_dir() {
+        let hash = sha256_file(path)
+            .map_err(|e| format!("Failed to hash '{}': {}", rel, e))?;
+        return Ok(HashTreeNode { path: rel, is_dir: false, hash, children: Vec::new() });
+    }
+
+    let mut entries: Vec<_> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read directory: {}", e))?
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Dir entry error: {}", e))?;
+    entries.retain(|entry| !is_helper_entry(&entry.file_name().to_string_lossy()));
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut hasher = sha2::Sha256::new();
+    let mut children = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let child = hash_tree_node(data_dir, &entry.path())?;
+        hasher.update(entry.file_name().to_string_lossy().as_bytes());
+        hasher.update(if child.is_dir { b"\0d\0" } else { b"\0f\0" });
+        hasher.update(child.hash.as_bytes());
+        hasher.update(b"\n");
+        children.push(child);
+    }
+    Ok(HashTreeNode {
+        path: rel,
+        is_dir: true,
+        hash: hex_encode(&hasher.finalize()),
+        children,
+    })
+}
+
+/// Upper bound for page exports; 600 DPI is already ~5000px tall for A4
+const MAX_EXPORT_DPI: u32 = 600;
+
+/// Bind to a Pdfium library shipped next to the executable, falling back to a system install
+fn load_pdfium() -> Result<pdfium_render::prelude::Pdfium, String> {
+    use pdfium_render::prelude::Pdfium;
+
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    let bindings = Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(&exe_dir))
+        .or_else(|_| Pdfium::bind_to_system_library())
+        .map_err(|e| format!("Pdfium library not available: {}", e))?;
+    Ok(Pdfium::new(bindings))
+}
+
+#[derive(serde::Serialize)]
+struct ImageSize {
+    width: u32,
+    height: u32,
+}
+
+/// Render one page (1-based) of a PDF at `dpi` and save it as PNG at `dest_path`
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn pdf_export_page_image(
+    app: tauri::AppHandle,
+    filename: String,
+    page: u32,
+    dpi: u32,
+    dest_path: String,
+) -> Result<ImageSize, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    run_limited(&app, move || pdf_export_page_image_in(&root, &relative, page, dpi, &dest_path)).await
+}
+
+fn pdf_export_page_image_in(
+    data_dir: &Path,
+    filename: &str,
+    page: u32,
+    dpi: u32,
+    dest_path: &str,
+) -> Result<ImageSize, String> {
+    use pdfium_render::prelude::PdfRenderConfig;
+
+    let path = resolve_data_path(data_dir, filename)?;
+    let pdfium = load_pdfium()?;
+    let document = pdfium
+        .load_pdf_from_file(&path, None)
+        .map_err(|e| format!("Failed to open PDF '{}': {}", filename, e))?;
+    let page_count = document.pages().len();
+    if page == 0 || page as i64 > page_count as i64 {
+        return Err(format!("Page {} out of range (1-{})", page, page_count));
+    }
+    let pdf_page = document
+        .pages()
+        .get((page - 1) as _)
+        .map_err(|e| format!("Failed to load page {}: {}", page, e))?;
+
+    let dpi = dpi.clamp(1, MAX_EXPORT_DPI);
+    let config = PdfRenderConfig::new().scale_page_by_factor(dpi as f32 / 72.0);
+    let image = pdf_page
+        .render_with_config(&config)
+        .and_then(|bitmap| bitmap.as_image())
+        .map_err(|e| format!("Failed to render page {}: {}", page, e))?;
+    image
+        .save_with_format(dest_path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to save image to '{}': {}", dest_path, e))?;
+    Ok(ImageSize { width: image.width(), height: image.height() })
+}
+
+/// Cap on bytes inflated from a PDF's object and metadata streams during the
+/// PDF/A check, so a decompression bomb can't exhaust memory
+const PDFA_MAX_INFLATED_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(serde::Serialize)]
+struct PdfaReport {
+    /// Level declared in the XMP identification, e.g. "PDF/A-2B"; `None` if undeclared
+    conformance_level: Option<String>,
+    /// Problems a validator would reject. An empty list is not proof of compliance.
+    issues: Vec<String>,
+}
+
+/// Heuristic PDF/A check: looks for the XMP `pdfaid` identification, embedded
+/// font programs, an ICC output intent and features PDF/A forbids. It works on
+/// the raw bytes plus inflated object and metadata streams, not a full parse.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn pdf_check_pdfa(app: tauri::AppHandle, filename: String) -> Result<PdfaReport, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    run_limited(&app, move || pdf_check_pdfa_in(&root, &relative)).await
+}
+
+fn pdf_check_pdfa_in(data_dir: &Path, filename: &str) -> Result<PdfaReport, String> {
+    let raw = fs::read(resolve_data_path(data_dir, filename)?)
+        .map_err(|e| format!("Failed to read '{}': {}", filename, e))?;
+    if !raw.starts_with(b"%PDF-") {
+        return Err(format!("'{}' is not a PDF file", filename));
+    }
+    let pdf = pdf_searchable_bytes(&raw);
+    let mut issues = Vec::new();
+
+    let part = xmp_property(&pdf, b"pdfaid:part");
+    let conformance = xmp_property(&pdf, b"pdfaid:conformance");
+    let conformance_level = part
+        .as_ref()
+        .map(|part| format!("PDF/A-{}{}", part, conformance.as_deref().unwrap_or("").to_ascii_uppercase()));
+    if part.is_none() {
+        issues.push("No PDF/A identification (pdfaid:part) in the XMP metadata".to_string());
+    }
+
+    let descriptors = count_pdf_dict_type(&pdf, b"/FontDescriptor");
+    let embedded = count_bytes(&pdf, b"/FontFile");
+    // Every font except composite (Type0) and Type3 fonts needs a descriptor of its own
+    let fonts = count_bytes(&pdf, b"/BaseFont").saturating_sub(count_bytes(&pdf, b"/Type0"));
+    let missing = fonts.max(descriptors).saturating_sub(embedded);
+    if missing > 0 {
+        issues.push(format!("{} of {} fonts are not embedded", missing, fonts.max(descriptors)));
+    }
+
+    if count_bytes(&pdf, b"/OutputIntents") == 0 {
+        issues.push("No output intent; device colors have no ICC profile".to_string());
+    } else if count_bytes(&pdf, b"/GTS_PDFA1") == 0 || count_bytes(&pdf, b"/DestOutputProfile") == 0 {
+        issues.push("Output intent is not a GTS_PDFA1 intent with an ICC profile".to_string());
+    }
+
+    if count_bytes(&pdf, b"/Encrypt") > 0 {
+        issues.push("Document is encrypted".to_string());
+    }
+    if count_bytes(&pdf, b"/JavaScript") > 0 {
+        issues.push("Document contains JavaScript".to_string());
+    }
+    let part = part.as_deref();
+    if part == Some("1") && count_bytes(&pdf, b"/SMask") > 0 {
+        issues.push("PDF/A-1 does not allow transparency (soft masks found)".to_string());
+    }
+    if matches!(part, Some("1") | Some("2")) && count_bytes(&pdf, b"/EmbeddedFiles") > 0 {
+        issues.push("Embedded files require PDF/A-3 (PDF/A-2 only allows PDF/A attachments)".to_string());
+    }
+
+    Ok(PdfaReport { conformance_level, issues })
+}
+
+/// The raw PDF followed by the inflated contents of its object streams and
+/// metadata streams, where compressed PDFs keep font dictionaries and XMP
+fn pdf_searchable_bytes(raw: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut searchable = raw.to_vec();
+    let mut budget = PDFA_MAX_INFLATED_BYTES;
+    let mut from = 0;
+    while let Some(at) = find_bytes(raw, b"stream", from) {
+        from = at + b"stream".len();
+        if raw[..at].ends_with(b"end") {
+            continue;
+        }
+        let data_start = match &raw[from..] {
+            [b'\r', b'\n', ..] => from + 2,
+            [b'\n', ..] | [b'\r', ..] => from + 1,
+            _ => continue,
+        };
+        let dict_start = raw[..at]
+            .windows(3)
+            .rposition(|w| w == b"obj")
+            .filter(|&i| at - i <= 1024)
+            .unwrap_or(at.saturating_sub(1024));
+        let dict = &raw[dict_start..at];
+        let wanted = count_bytes(dict, b"/ObjStm") > 0 || count_bytes(dict, b"/Metadata") > 0;
+        if !wanted || count_bytes(dict, b"/FlateDecode") == 0 || budget == 0 {
+            continue;
+        }
+        let data_end = find_bytes(raw, b"endstream", data_start).unwrap_or(raw.len());
+        let mut inflated = Vec::new();
+        // Truncated or corrupt streams still yield what inflated before the error
+        let _ = flate2::read::ZlibDecoder::new(&raw[data_start..data_end])
+            .take(budget)
+            .read_to_end(&mut inflated);
+        budget -= inflated.len() as u64;
+        searchable.push(b'\n');
+        searchable.extend_from_slice(&inflated);
+        from = data_end;
+    }
+    searchable
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+fn count_bytes(haystack: &[u8], needle: &[u8]) -> usize {
+    haystack.windows(needle.len()).filter(|w| *w == needle).count()
+}
+
+/// Dictionaries declaring `/Type <name>`, with or without whitespace between
+fn count_pdf_dict_type(pdf: &[u8], name: &[u8]) -> usize {
+    let mut count = 0;
+    let mut from = 0;
+    while let Some(at) = find_bytes(pdf, name, from) {
+        from = at + name.len();
+        let before = &pdf[..at];
+        let trimmed = before.len() - before.iter().rev().take_while(|b| b.is_ascii_whitespace()).count();
+        if before[..trimmed].ends_with(b"/Type") {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Value of an XMP simple property written either as an attribute
+/// (`pdfaid:part="2"`) or an element (`<pdfaid:part>2</pdfaid:part>`)
+fn xmp_property(xmp: &[u8], name: &[u8]) -> Option<String> {
+    let mut from = 0;
+    while let Some(at) = find_bytes(xmp, name, from) {
+        from = at + name.len();
+        let rest = &xmp[from..];
+        let skip = rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        let value_start = match rest.get(skip) {
+            Some(b'=') => {
+                let after = &rest[skip + 1..];
+                let ws = after.iter().take_while(|b| b.is_ascii_whitespace()).count();
+                match after.get(ws) {
+                    Some(b'"') | Some(b'\'') => skip + 1 + ws + 1,
+                    _ => continue,
+                }
+            }
+            Some(b'>') => skip + 1,
+            _ => continue,
+        };
+        let value: String = rest[value_start..]
+            .iter()
+            .skip_while(|b| b.is_ascii_whitespace())
+            .take_while(|b| b.is_ascii_alphanumeric())
+            .map(|&b| b as char)
+            .collect();
+        if !value.is_empty() {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Pixels sampled per image when computing a palette; larger images are strided
+const PALETTE_SAMPLE_PIXELS: u64 = 65_536;
+
+fn open_image(path: &Path) -> Result<image::DynamicImage, String> {
+    image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("Failed to open image '{}': {}", path.display(), e))?
+        .decode()
+        .map_err(|e| format!("Failed to decode image '{}': {}", path.display(), e))
+}
+
+#[derive(serde::Serialize)]
+struct PaletteColor {
+    /// "#rrggbb"
+    hex: String,
+    /// Share of sampled pixels, 0..1
+    weight: f64,
+}
+
+/// Dominant colors of an image, most common first. Colors are bucketed to 4 bits
+/// per channel and each bucket reports the average of its pixels.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn extract_image_palette(
+    app: tauri::AppHandle,
+    filename: String,
+    count: usize,
+) -> Result<Vec<PaletteColor>, String> {
+    ensure_unlocked(&app)?;
+    let (root, relative) = read_root(&app, get_data_dir(&app)?, &filename)?;
+    let path = resolve_data_path(&root, &relative)?;
+    run_limited(&app, move || extract_image_palette_in(&path, count)).await
+}
+
+fn extract_image_palette_in(path: &Path, count: usize) -> Result<Vec<PaletteColor>, String> {
+    let rgba = open_image(path)?.to_rgba8();
+
+    let total = rgba.width() as u64 * rgba.height() as u64;
+    let stride = (total / PALETTE_SAMPLE_PIXELS).max(1) as usize;
+    // bucket -> (pixel count, r sum, g sum, b sum)
+    let mut buckets: std::collections::HashMap<u16, (u64, u64, u64, u64)> = std::collections::HashMap::new();
+    let mut sampled = 0u64;
+    for pixel in rgba.pixels().step_by(stride) {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let key = ((r as u16 >> 4) << 8) | ((g as u16 >> 4) << 4) | (b as u16 >> 4);
+        let entry = buckets.entry(key).or_insert((0, 0, 0, 0));
+        entry.0 += 1;
+        entry.1 += r as u64;
+        entry.2 += g as u64;
+        entry.3 += b as u64;
+        sampled += 1;
+    }
+    if sampled == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut colors: Vec<_> = buckets.into_values().collect();
+    colors.sort_by_key(|c| std::cmp::Reverse(c.0));
+    Ok(colors
+        .into_iter()
+        .take(count)
+        .map(|(n, r, g, b)| PaletteColor {
+            hex: format!("#{:02x}{:02x}{:02x}", r / n, g / n, b / n),
+            weight: n as f64 / sampled as f64,
+        })
+        .collect())
+}
+
+#[derive(serde::Serialize)]
+struct ImageDimensions {
+    width: u32,
+    height: u32,
+    /// "png", "jpeg", "gif" or "webp"
+    format: String,
+}
+
+/// Width, height and format read from the image header alone, without decoding
+/// pixels. PNG, JPEG, GIF and WebP are supported.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_image_dimensions(app: tauri::AppHandle, filename: String) -> Result<ImageDimensions, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    image_dimensions_in(&data_dir, &filename)
+}
+
+fn image_dimensions_in(data_dir: &Path, filename: &str) -> Result<ImageDimensions, String> {
+    use imagesize::ImageType;
+
+    let file = fs::File::open(resolve_data_path(data_dir, filename)?)
+        .map_err(|e| format!("Failed to open image '{}': {}", filename, e))?;
+    let mut reader = std::io::BufReader::new(file);
+    let kind = imagesize::reader_type(&mut reader)
+        .map_err(|e| format!("Unrecognized image format for '{}': {}", filename, e))?;
+    let format = match kind {
+        ImageType::Png => "png",
+        ImageType::Jpeg => "jpeg",
+        ImageType::Gif => "gif",
+        ImageType::Webp => "webp",
+        other => return Err(format!("Unsupported image format for '{}': {:?}", filename, other)),
+    };
+    let size = kind
+        .reader_size(&mut reader)
+        .map_err(|e| format!("Failed to read image header of '{}': {}", filename, e))?;
+    Ok(ImageDimensions {
+        width: size.width as u32,
+        height: size.height as u32,
+        format: format.to_string(),
+    })
+}
+
+/// SQLite index kept alongside the data files
+const INDEX_DB_FILE: &str = ".index.db";
+
+const INDEX_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS reading_progress (
+        file_id INTEGER PRIMARY KEY REFERENCES files(id),
+        page INTEGER NOT NULL,
+        scroll_fraction REAL NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS file_metadata (
+        path TEXT PRIMARY KEY,
+        size_bytes INTEGER NOT NULL,
+        modified_millis INTEGER NOT NULL,
+        mime_type TEXT NOT NULL,
+        sniffed INTEGER NOT NULL,
+        line_count INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS import_history (
+        fingerprint TEXT PRIMARY KEY,
+        filename TEXT NOT NULL,
+        imported_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        path TEXT NOT NULL UNIQUE,
+        fingerprint TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pins (
+        file_id INTEGER PRIMARY KEY REFERENCES files(id),
+        pinned_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS paper_summaries (
+        file_id INTEGER PRIMARY KEY REFERENCES files(id),
+        summary TEXT NOT NULL,
+        content_hash TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+";
+
+fn open_index(data_dir: &Path) -> Result<rusqlite::Connection, String> {
+    let conn = rusqlite::Connection::open(data_dir.join(INDEX_DB_FILE))
+        .map_err(|e| format!("Failed to open index: {}", e))?;
+    conn.execute_batch(INDEX_SCHEMA)
+        .map_err(|e| format!("Failed to initialize index: {}", e))?;
+    // Indexes from before line counts lack the column; the table is only a
+    // cache, so its rows are dropped and rebuilt on the next listing
+    let has_line_count: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('file_metadata') WHERE name = 'line_count'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Failed to initialize index: {}", e))?;
+    if !has_line_count {
+        conn.execute_batch("DELETE FROM file_metadata; ALTER TABLE file_metadata ADD COLUMN line_count INTEGER;")
+            .map_err(|e| format!("Failed to migrate index: {}", e))?;
+    }
+    let progress_by_path: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('reading_progress') WHERE name = 'paper_id'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Failed to initialize index: {}", e))?;
+    if progress_by_path {
+        migrate_reading_progress(&conn, data_dir)?;
+    }
+    Ok(conn)
+}
+
+/// Move reading progress from the old table keyed by path onto file ids, so it
+/// follows renames. Rows for papers that no longer exist are dropped.
+fn migrate_reading_progress(conn: &rusqlite::Connection, data_dir: &Path) -> Result<(), String> {
+    let err = |e: rusqlite::Error| format!("Failed to migrate reading progress: {}", e);
+    let tx = conn.unchecked_transaction().map_err(err)?;
+    tx.execute_batch("ALTER TABLE reading_progress RENAME TO reading_progress_by_path;")
+        .map_err(err)?;
+    tx.execute_batch(INDEX_SCHEMA).map_err(err)?;
+    let rows: Vec<(String, u32, f64, i64)> = tx
+        .prepare("SELECT paper_id, page, scroll_fraction, updated_at FROM reading_progress_by_path")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+                .collect()
+        })
+        .map_err(err)?;
+    for (paper_id, page, scroll_fraction, updated_at) in rows {
+        if !resolve_data_path(data_dir, &paper_id).is_ok_and(|path| path.is_file()) {
+            continue;
+        }
+        let id = file_id(&tx, data_dir, &paper_id)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO reading_progress (file_id, page, scroll_fraction, updated_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![id, page, scroll_fraction, updated_at],
+        )
+        .map_err(err)?;
+    }
+    tx.execute_batch("DROP TABLE reading_progress_by_path;").map_err(err)?;
+    tx.commit().map_err(err)
+}
+
+#[derive(serde::Serialize)]
+struct IndexCompaction {
+    before_bytes: u64,
+    after_bytes: u64,
+    /// File records dropped because their file is gone
+    dropped_files: usize,
+}
+
+/// Rewrite the library index without entries for deleted files, then VACUUM it
+/// so the freed pages are returned to the filesystem. Files renamed outside the
+/// app keep their records. This rewrites the whole database, so run it as
+/// occasional maintenance rather than after every change.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn compact_search_index(app: tauri::AppHandle) -> Result<IndexCompaction, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    run_limited(&app, move || compact_search_index_in(&data_dir)).await
+}
+
+fn compact_search_index_in(data_dir: &Path) -> Result<IndexCompaction, String> {
+    let index_path = data_dir.join(INDEX_DB_FILE);
+    let size = |path: &Path| fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let before_bytes = size(&index_path);
+    let err = |e: rusqlite::Error| format!("Failed to compact index: {}", e);
+
+    let conn = open_index(data_dir)?;
+    let records: Vec<(i64, String, String)> = conn
+        .prepare("SELECT id, path, fingerprint FROM files")
+        .and_then(|mut stmt| stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?.collect())
+        .map_err(err)?;
+    let mut gone = Vec::new();
+    for (id, path, fingerprint) in records {
+        if locate_file(&conn, data_dir, id, &path, &fingerprint)?.is_none() {
+            gone.push(id);
+        }
+    }
+    let cached: Vec<String> = conn
+        .prepare("SELECT path FROM file_metadata")
+        .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+        .map_err(err)?;
+
+    let tx = conn.unchecked_transaction().map_err(err)?;
+    for id in &gone {
+        for table in ["reading_progress", "pins", "paper_summaries", "files"] {
+            let column = if table == "files" { "id" } else { "file_id" };
+            tx.execute(&format!("DELETE FROM {} WHERE {} = ?1", table, column), [id])
+                .map_err(err)?;
+        }
+    }
+    for path in cached.iter().filter(|path| !data_dir.join(path).is_file()) {
+        tx.execute("DELETE FROM file_metadata WHERE path = ?1", [path]).map_err(err)?;
+    }
+    tx.commit().map_err(err)?;
+    conn.execute_batch("VACUUM;").map_err(err)?;
+    drop(conn);
+
+    Ok(IndexCompaction { before_bytes, after_bytes: size(&index_path), dropped_files: gone.len() })
+}
+
+/// Milliseconds since the Unix epoch
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(serde::Serialize)]
+struct ReadingProgress {
+    /// The paper's current path
+    paper_id: String,
+    page: u32,
+    scroll_fraction: f64,
+    updated_at: u64,
+}
+
+/// Remember where the reader left off in a paper. `paper_id` is its data-dir
+/// path; progress is stored against the file's stable id, so it survives renames.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn set_reading_progress(
+    app: tauri::AppHandle,
+    paper_id: String,
+    page: u32,
+    scroll_fraction: f64,
+) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    if !scroll_fraction.is_finite() {
+        return Err(format!("Invalid scroll fraction: {}", scroll_fraction));
+    }
+    let data_dir = get_data_dir(&app)?;
+    if !resolve_data_path(&data_dir, &paper_id)?.is_file() {
+        return Err(format!("File does not exist: {}", paper_id));
+    }
+    let conn = open_index(&data_dir)?;
+    let id = file_id(&conn, &data_dir, &paper_id)?;
+    conn.execute(
+        "INSERT INTO reading_progress (file_id, page, scroll_fraction, updated_at)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(file_id) DO UPDATE SET
+            page = excluded.page,
+            scroll_fraction = excluded.scroll_fraction,
+            updated_at = excluded.updated_at",
+        rusqlite::params![id, page, scroll_fraction.clamp(0.0, 1.0), now_millis() as i64],
+    )
+    .map_err(|e| format!("Failed to save reading progress: {}", e))?;
+    Ok(())
+}
+
+/// Last saved position for a paper, or `None` if it was never opened
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_reading_progress(app: tauri::AppHandle, paper_id: String) -> Result<Option<ReadingProgress>, String> {
+    use rusqlite::OptionalExtension;
+
+    ensure_unlocked(&app)?;
+    let conn = open_index(&get_data_dir(&app)?)?;
+    conn.query_row(
+        "SELECT page, scroll_fraction, reading_progress.updated_at
+         FROM reading_progress JOIN files ON files.id = reading_progress.file_id
+         WHERE files.path = ?1",
+        [&paper_id],
+        |row| {
+            Ok(ReadingProgress {
+                paper_id: paper_id.clone(),
+                page: row.get(0)?,
+                scroll_fraction: row.get(1)?,
+                updated_at: row.get::<_, i64>(2)? as u64,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| format!("Failed to load reading progress: {}", e))
+}
+
+/// Stable id for a data file, registering it on first use. The id outlives
+/// renames: see `locate_file`.
+fn file_id(conn: &rusqlite::Connection, data_dir: &Path, rel: &str) -> Result<i64, String> {
+    let fingerprint = import_fingerprint(&data_dir.join(rel))
+        .map_err(|e| format!("Failed to fingerprint '{}': {}", rel, e))?;
+    conn.execute(
+        "INSERT INTO files (path, fingerprint) VALUES (?1, ?2)
+         ON CONFLICT(path) DO UPDATE SET fingerprint = excluded.fingerprint",
+        rusqlite::params![rel, fingerprint],
+    )
+    .map_err(|e| format!("Failed to register '{}': {}", rel, e))?;
+    conn.query_row("SELECT id FROM files WHERE path = ?1", [rel], |row| row.get(0))
+        .map_err(|e| format!("Failed to register '{}': {}", rel, e))
+}
+
+/// Current path of a registered file. If it is no longer at its recorded path
+/// (renamed outside the app), the data dir is searched for a file with the same
+/// fingerprint and the record follows it. `None` if the file is gone.
+fn locate_file(
+    conn: &rusqlite::Connection,
+    data_dir: &Path,
+    id: i64,
+    path: &str,
+    fingerprint: &str,
+) -> Result<Option<String>, String> {
+    if data_dir.join(path).is_file() {
+        return Ok(Some(path.to_string()));
+    }
+    let size: Option<u64> = fingerprint.split('-').next().and_then(|s| s.parse().ok());
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+    for candidate in files {
+        if fs::metadata(&candidate).map(|m| Some(m.len()) != size).unwrap_or(true) {
+            continue;
+        }
+        if import_fingerprint(&candidate).ok().as_deref() != Some(fingerprint) {
+            continue;
+        }
+        let rel = relative_data_path(data_dir, &candidate)?;
+        // Another record may already own the new path; leave both untouched then
+        let moved = conn
+            .execute("UPDATE OR IGNORE files SET path = ?1 WHERE id = ?2", rusqlite::params![rel, id])
+            .map_err(|e| format!("Failed to update file record: {}", e))?;
+        if moved > 0 {
+            return Ok(Some(rel));
+        }
+    }
+    Ok(None)
+}
+
+/// Keep file records in step with a rename made by the app
+fn record_file_rename(data_dir: &Path, from: &str, to: &str) -> Result<(), String> {
+    open_index(data_dir)?
+        .execute("UPDATE OR IGNORE files SET path = ?2 WHERE path = ?1", [from, to])
+        .map_err(|e| format!("Failed to update file record: {}", e))?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct PinnedFile {
+    id: i64,
+    /// Current data-dir relative path
+    path: String,
+    pinned_at: u64,
+}
+
+/// Pin a file to the fast-access list; pinning it again keeps its position
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn pin_file(app: tauri::AppHandle, filename: String) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    if !resolve_data_path(&data_dir, &filename)?.is_file() {
+        return Err(format!("File does not exist: {}", filename));
+    }
+    let conn = open_index(&data_dir)?;
+    let id = file_id(&conn, &data_dir, &filename)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO pins (file_id, pinned_at) VALUES (?1, ?2)",
+        rusqlite::params![id, now_millis() as i64],
+    )
+    .map_err(|e| format!("Failed to pin '{}': {}", filename, e))?;
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn unpin_file(app: tauri::AppHandle, filename: String) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let conn = open_index(&get_data_dir(&app)?)?;
+    conn.execute("DELETE FROM pins WHERE file_id IN (SELECT id FROM files WHERE path = ?1)", [&filename])
+        .map_err(|e| format!("Failed to unpin '{}': {}", filename, e))?;
+    Ok(())
+}
+
+/// Pinned files in the order they were pinned, at their current paths. Pins
+/// whose file no longer exists are removed.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn list_pinned(app: tauri::AppHandle) -> Result<Vec<PinnedFile>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    list_pinned_in(&data_dir)
+}
+
+fn list_pinned_in(data_dir: &Path) -> Result<Vec<PinnedFile>, String> {
+    let conn = open_index(data_dir)?;
+    let rows: Vec<(i64, String, String, i64)> = conn
+        .prepare(
+            "SELECT files.id, files.path, files.fingerprint, pins.pinned_at
+             FROM pins JOIN files ON files.id = pins.file_id ORDER BY pins.pinned_at",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+                .collect()
+        })
+        .map_err(|e| format!("Failed to read pins: {}", e))?;
+
+    let mut pinned = Vec::with_capacity(rows.len());
+    for (id, path, fingerprint, pinned_at) in rows {
+        match locate_file(&conn, data_dir, id, &path, &fingerprint)? {
+            Some(path) => pinned.push(PinnedFile { id, path, pinned_at: pinned_at as u64 }),
+            None => {
+                conn.execute("DELETE FROM pins WHERE file_id = ?1", [id])
+                    .map_err(|e| format!("Failed to drop pin: {}", e))?;
+            }
+        }
+    }
+    Ok(pinned)
+}
+
+/// Cache a generated summary for a paper (a data-dir relative path). It stays
+/// attached to the paper through renames and is dropped once the paper's
+/// content changes; see `get_paper_summary`.
+#[tauri::command]
+#[tracing::instrument(skip(app, summary), err)]
+async fn set_paper_summary(app: tauri::AppHandle, paper_id: String, summary: String) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || set_paper_summary_in(&data_dir, &paper_id, &summary)).await
+}
+
+fn set_paper_summary_in(data_dir: &Path, paper_id: &str, summary: &str) -> Result<(), String> {
+    let path = resolve_data_path(data_dir, paper_id)?;
+    if !path.is_file() {
+        return Err(format!("File does not exist: {}", paper_id));
+    }
+    let content_hash = sha256_file(&path).map_err(|e| format!("Failed to hash '{}': {}", paper_id, e))?;
+    let conn = open_index(data_dir)?;
+    let id = file_id(&conn, data_dir, paper_id)?;
+    conn.execute(
+        "INSERT INTO paper_summaries (file_id, summary, content_hash, updated_at)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(file_id) DO UPDATE SET
+            summary = excluded.summary,
+            content_hash = excluded.content_hash,
+            updated_at = excluded.updated_at",
+        rusqlite::params![id, summary, content_hash, now_millis() as i64],
+    )
+    .map_err(|e| format!("Failed to save summary for '{}': {}", paper_id, e))?;
+    Ok(())
+}
+
+/// Cached summary for a paper, or `None` if there is none or the paper has
+/// changed since it was summarized (the stale summary is removed)
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn get_paper_summary(app: tauri::AppHandle, paper_id: String) -> Result<Option<String>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || get_paper_summary_in(&data_dir, &paper_id)).await
+}
+
+fn get_paper_summary_in(data_dir: &Path, paper_id: &str) -> Result<Option<String>, String> {
+    use rusqlite::OptionalExtension;
+
+    let path = resolve_data_path(data_dir, paper_id)?;
+    let conn = open_index(data_dir)?;
+    let cached: Option<(i64, String, String)> = conn
+        .query_row(
+            "SELECT files.id, paper_summaries.summary, paper_summaries.content_hash
+             FROM paper_summaries JOIN files ON files.id = paper_summaries.file_id
+             WHERE files.path = ?1",
+            [paper_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to load summary for '{}': {}", paper_id, e))?;
+    let Some((id, summary, content_hash)) = cached else {
+        return Ok(None);
+    };
+    if sha256_file(&path).ok().as_deref() == Some(content_hash.as_str()) {
+        return Ok(Some(summary));
+    }
+    conn.execute("DELETE FROM paper_summaries WHERE file_id = ?1", [id])
+        .map_err(|e| format!("Failed to drop stale summary: {}", e))?;
+    Ok(None)
+}
+
+/// Extensions treated as text when scanning notes; everything else is assumed binary
+const TEXT_EXTENSIONS: &[&str] = &[
+    "md", "markdown", "txt", "html", "htm", "json", "jsonl", "csv", "tsv", "xml", "bib", "tex", "yaml", "yml",
+];
+
+fn is_text_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.as_str()))
+}
+
+#[derive(serde::Serialize)]
+struct EncodingIssue {
+    path: String,
+    /// Best guess from chardetng, e.g. "EUC-KR" or "windows-1252"
+    guessed_encoding: String,
+    error: String,
+}
+
+/// Text files that are not valid UTF-8, with a guess at their actual encoding
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn scan_encoding_issues(app: tauri::AppHandle) -> Result<Vec<EncodingIssue>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let mut paths = Vec::new();
+    collect_files(&data_dir, true, &mut paths)?;
+
+    let mut issues = Vec::new();
+    for path in paths.into_iter().filter(|p| is_text_file(p)) {
+        let bytes = fs::read(&path)
+            .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        if let Err(e) = std::str::from_utf8(&bytes) {
+            issues.push(EncodingIssue {
+                path: relative_data_path(&data_dir, &path)?,
+                guessed_encoding: guess_legacy_encoding(&bytes).name().to_string(),
+                error: e.to_string(),
+            });
+        }
+    }
+    Ok(issues)
+}
+
+/// Guess the encoding of bytes already known not to be UTF-8
+fn guess_legacy_encoding(bytes: &[u8]) -> &'static encoding_rs::Encoding {
+    let mut detector = chardetng::EncodingDetector::new(chardetng::Iso2022JpDetection::Allow);
+    detector.feed(bytes, true);
+    detector.guess(None, chardetng::Utf8Detection::Deny)
+}
+
+#[derive(serde::Serialize)]
+struct ReencodeResult {
+    path: String,
+    converted: bool,
+    source_encoding: Option<String>,
+    /// Copy of the original bytes, relative to the data dir
+    backup_path: Option<String>,
+    error: Option<String>,
+}
+
+/// Rewrite legacy-encoded notes as UTF-8, keeping a backup of each original under `.backups/`
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn reencode_to_utf8(app: tauri::AppHandle, filenames: Vec<String>) -> Result<Vec<ReencodeResult>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let stamp = now_millis();
+    Ok(filenames
+        .into_iter()
+        .map(|filename| {
+            let mut result = ReencodeResult {
+                path: filename.clone(),
+                converted: false,
+                source_encoding: None,
+                backup_path: None,
+                error: None,
+            };
+            let reencoded = ensure_writable(&app, &filename)
+                .and_then(|()| reencode_file(&data_dir, &filename, stamp, &mut result));
+            if let Err(e) = reencoded {
+                result.error = Some(e);
+            }
+            result
+        })
+        .collect())
+}
+
+fn reencode_file(data_dir: &Path, filename: &str, stamp: u64, result: &mut ReencodeResult) -> Result<(), String> {
+    let path = resolve_data_path(data_dir, filename)?;
+    let bytes = fs::read(&path)
+        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    if std::str::from_utf8(&bytes).is_ok() {
+        // Already UTF-8, nothing to do
+        return Ok(());
+    }
+
+    let encoding = guess_legacy_encoding(&bytes);
+    result.source_encoding = Some(encoding.name().to_string());
+    let (text, _, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        return Err(format!("'{}' is not valid {}; left unchanged", filename, encoding.name()));
+    }
+
+    let backup_rel = format!(".backups/{}.{}", filename, stamp);
+    write_within_quota(&data_dir.join(&backup_rel), None, &bytes, false, true, &backup_rel)?;
+    result.backup_path = Some(backup_rel);
+
+    write_within_quota(&path, None, text.as_bytes(), false, true, filename)?;
+    result.converted = true;
+    Ok(())
+}
+
+/// Content-addressed attachment store; dot-prefixed so file listings skip it
+const BLOBS_DIR: &str = ".blobs";
+
+/// Path of a blob, rejecting anything that isn't a lowercase hex SHA-256
+fn blob_path(data_dir: &Path, hash: &str) -> Result<PathBuf, String> {
+    let valid = hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    if !valid {
+        return Err(format!("Invalid blob hash '{}'", hash));
+    }
+    Ok(data_dir.join(BLOBS_DIR).join(hash))
+}
+
+/// Store base64 content under its SHA-256 and return the hash. Identical content
+/// is only written once.
+#[tauri::command]
+#[tracing::instrument(skip(app, base64_content), err)]
+fn store_blob(app: tauri::AppHandle, base64_content: String) -> Result<String, String> {
+    use sha2::Digest;
+
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let bytes = general_purpose::STANDARD
+        .decode(base64_content.as_bytes())
+        .map_err(|e| format!("Invalid base64 content: {}", e))?;
+    let hash = hex_encode(&sha2::Sha256::digest(&bytes));
+    let path = blob_path(&data_dir, &hash)?;
+    if !path.exists() {
+        ensure_free_space(&data_dir, bytes.len() as u64)?;
+        write_within_quota(&path, None, &bytes, false, true, &hash)?;
+    } else {
+        // Restart the GC grace period: the note about to reference this blob may not be saved yet
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+            .map_err(|e| format!("Failed to refresh blob '{}': {}", hash, e))?;
+    }
+    Ok(hash)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn read_blob(app: tauri::AppHandle, hash: String) -> Result<String, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let bytes = fs::read(blob_path(&data_dir, &hash)?)
+        .map_err(|e| format!("Failed to read blob '{}': {}", hash, e))?;
+    Ok(general_purpose::STANDARD.encode(bytes))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn blob_exists(app: tauri::AppHandle, hash: String) -> Result<bool, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    Ok(blob_path(&data_dir, &hash)?.exists())
+}
+
+/// Collect every 64-character hex run in `bytes`, the form blob hashes take in notes
+fn collect_blob_hashes(bytes: &[u8], hashes: &mut std::collections::HashSet<String>) {
+    for run in bytes.split(|b| !b.is_ascii_hexdigit()) {
+        if run.len() == 64 {
+            hashes.insert(String::from_utf8_lossy(run).to_ascii_lowercase());
+        }
+    }
+}
+
+/// Blobs stored this recently are never collected, since the note referencing
+/// one is usually written just after `store_blob` returns
+const BLOB_GC_GRACE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Note content for `gc_blobs`, decompressing `.z` and `.gz` files like `read_data_file`
+fn blob_scan_bytes(path: &Path) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let bytes = fs::read(path)?;
+    let ext = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    let mut content = Vec::new();
+    match ext.as_deref() {
+        Some("z") => flate2::read::ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut content)?,
+        Some("gz") if bytes.starts_with(&[0x1f, 0x8b]) => {
+            flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut content)?
+        }
+        _ => return Ok(bytes),
+    };
+    Ok(content)
+}
+
+/// Delete blobs no note or paper references and return the bytes reclaimed.
+/// Compressed notes are scanned too. Any unreadable file aborts the whole run,
+/// since the blobs it references would otherwise look orphaned. Blobs younger
+/// than `BLOB_GC_GRACE` are kept.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn gc_blobs(app: tauri::AppHandle) -> Result<u64, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let blobs_dir = data_dir.join(BLOBS_DIR);
+    if !blobs_dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut files = Vec::new();
+    collect_files(&data_dir, true, &mut files)?;
+    let mut referenced = std::collections::HashSet::new();
+    let is_compressed = |p: &Path| {
+        p.extension().is_some_and(|e| e.eq_ignore_ascii_case("z") || e.eq_ignore_ascii_case("gz"))
+    };
+    for path in files.iter().filter(|p| is_text_file(p) || is_compressed(p)) {
+        let bytes = blob_scan_bytes(path).map_err(|e| {
+            format!("Aborting blob GC, failed to scan '{}': {}", path.display(), e)
+        })?;
+        collect_blob_hashes(&bytes, &mut referenced);
+    }
+
+    let entries = fs::read_dir(&blobs_dir)
+        .map_err(|e| format!("Failed to read blob store: {}", e))?;
+    let mut reclaimed = 0u64;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Dir entry error: {}", e))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Leave temp files from in-flight writes and anything that isn't a blob alone
+        if blob_path(&data_dir, &name).is_err() || referenced.contains(&name) {
+            continue;
+        }
+        let metadata = entry.metadata().map_err(|e| format!("Failed to stat blob '{}': {}", name, e))?;
+        let recent = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map_or(true, |age| age < BLOB_GC_GRACE);
+        if recent {
+            continue;
+        }
+        let size = metadata.len();
+        fs::remove_file(entry.path())
+            .map_err(|e| format!("Failed to delete blob '{}': {}", name, e))?;
+        reclaimed += size;
+    }
+    log::info!("[gc_blobs] Reclaimed {} bytes", reclaimed);
+    Ok(reclaimed)
+}
+
+/// Install the tracing subscriber. Command errors always go to the error log
+/// (see `ErrorLogLayer`). When `RUST_LOG` is set, command spans are also written
+/// as JSON lines to `trace.jsonl` in the app log dir, filtered by it; each span's
+/// close event carries its busy/idle time.
+fn init_tracing(app: &tauri::AppHandle, data_dir: &Path) -> Result<(), String> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::Layer;
+
+    let trace_layer = if std::env::var_os("RUST_LOG").is_some() {
+        let log_dir = app
+            .path()
+            .app_log_dir()
+            .map_err(|e| format!("Failed to get app log dir: {}", e))?;
+        fs::create_dir_all(&log_dir)
+            .map_err(|e| format!("Failed to create log dir: {}", e))?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_dir.join("trace.jsonl"))
+            .map_err(|e| format!("Failed to open trace file: {}", e))?;
+        let layer = tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_events(FmtSpan::CLOSE)
+            .with_writer(std::sync::Mutex::new(file))
+            .with_filter(tracing_subscriber::EnvFilter::from_default_env());
+        Some(layer)
+    } else {
+        None
+    };
+    tracing_subscriber::registry()
+        .with(ErrorLogLayer::new(data_dir))
+        .with(trace_layer)
+        .try_init()
+        .map_err(|e| format!("Failed to install tracing subscriber: {}", e))
+}
+
+/// Rolling log of command failures in the data dir, one JSON entry per line
+const ERROR_LOG_FILE: &str = ".error_log.jsonl";
+
+/// Size at which the error log is rotated to `<ERROR_LOG_FILE>.1`
+const ERROR_LOG_MAX_BYTES: u64 = 512 * 1024;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ErrorLogEntry {
+    /// Unix milliseconds
+    timestamp: u64,
+    command: String,
+    message: String,
+}
+
+/// Appends the errors commands return (recorded by `#[tracing::instrument(err)]`)
+/// and any other error-level event logged directly in a command span to `ERROR_LOG_FILE`
+struct ErrorLogLayer {
+    path: PathBuf,
+    write: std::sync::Mutex<()>,
+    /// Set once a failed append has been reported, so a broken log file warns once
+    failure_reported: std::sync::atomic::AtomicBool,
+}
+
+impl ErrorLogLayer {
+    fn new(data_dir: &Path) -> Self {
+        ErrorLogLayer {
+            path: data_dir.join(ERROR_LOG_FILE),
+            write: std::sync::Mutex::new(()),
+            failure_reported: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    fn append(&self, entry: &ErrorLogEntry) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let _guard = self.write.lock().unwrap_or_else(|e| e.into_inner());
+        if fs::metadata(&self.path).is_ok_and(|m| m.len() >= ERROR_LOG_MAX_BYTES) {
+            fs::rename(&self.path, rotated_error_log(&self.path))?;
+        }
+        let mut line = serde_json::to_string(entry).map_err(std::io::Error::other)?;
+        line.push('\n');
+        fs::OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(line.as_bytes())
+    }
+}
+
+fn rotated_error_log(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// Pulls the `error` field that `instrument(err)` records, or the message of a
+/// plain error event
+#[derive(Default)]
+struct ErrorMessageVisitor {
+    error: Option<String>,
+    message: Option<String>,
+}
+
+impl tracing::field::Visit for ErrorMessageVisitor {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        match field.name() {
+            "error" => self.error = Some(value.to_string()),
+            "message" => self.message = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "error" => self.error = Some(format!("{:?}", value)),
+            "message" => self.message = Some(format!("{:?}", value)),
+            _ => {}
+        }
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for ErrorLogLayer
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if *event.metadata().level() != tracing::Level::ERROR {
+            return;
+        }
+        // Only events directly in the command's span: an error raised in a nested
+        // span reaches the command's own `err` event anyway, so this logs it once
+        let Some(scope) = ctx.event_scope(event) else {
+            return;
+        };
+        let spans: Vec<_> = scope.collect();
+        let [command] = spans.as_slice() else {
+            return;
+        };
+        let mut visitor = ErrorMessageVisitor::default();
+        event.record(&mut visitor);
+        let Some(message) = visitor.error.or(visitor.message) else {
+            return;
+        };
+        let entry = ErrorLogEntry { timestamp: now_millis(), command: command.name().to_string(), message };
+        if let Err(e) = self.append(&entry) {
+            // Warn level, so this doesn't come back here through the log bridge
+            if !self.failure_reported.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                log::warn!("[error_log] Failed to write error log: {}", e);
+            }
+        }
+    }
+}
+
+/// The `max_lines` most recent command errors, newest first
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn get_error_log(app: tauri::AppHandle, max_lines: usize) -> Result<Vec<ErrorLogEntry>, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    read_error_log(&data_dir.join(ERROR_LOG_FILE), max_lines)
+}
+
+fn read_error_log(path: &Path, max_lines: usize) -> Result<Vec<ErrorLogEntry>, String> {
+    let mut lines = Vec::new();
+    for file in [path.to_path_buf(), rotated_error_log(path)] {
+        if lines.len() >= max_lines {
+            break;
+        }
+        match read_lines_reversed(&file, 0, max_lines - lines.len()) {
+            Ok(more) => lines.extend(more),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read error log: {}", e)),
+        }
+    }
+    // A line cut short by a crash mid-write is skipped rather than failing the read
+    Ok(lines.iter().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+/// Results per page when `search_data_files` is called without a limit
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// Bytes of context kept on each side of the first match in a snippet
+const SNIPPET_CONTEXT_BYTES: usize = 80;
+
+#[derive(serde::Serialize)]
+struct SearchHit {
+    path: String,
+    /// Total occurrences of all query terms
+    match_count: usize,
+    /// How many distinct query terms the file contains
+    terms_matched: usize,
+    snippet: String,
+    /// Term occurrences within `snippet`, in order and non-overlapping
+    highlights: Vec<SnippetHighlight>,
+}
+
+/// Offsets are UTF-16 code units so they index JavaScript strings directly
+#[derive(serde::Serialize)]
+struct SnippetHighlight {
+    start: usize,
+    length: usize,
+}
+
+#[derive(serde::Serialize)]
+struct SearchPage {
+    /// Matching files across all pages
+    total_hits: usize,
+    hits: Vec<SearchHit>,
+}
+
+/// Case-insensitive (ASCII) search over text files. Files matching more distinct
+/// terms rank first, then by total matches. Only the requested page gets snippets.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn search_data_files(
+    app: tauri::AppHandle,
+    query: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<SearchPage, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    run_limited(&app, move || search_data_files_in(&data_dir, &query, offset, limit)).await
+}
+
+fn search_data_files_in(data_dir: &Path, query: &str, offset: usize, limit: usize) -> Result<SearchPage, String> {
+    use rayon::prelude::*;
+
+    let terms: Vec<&str> = query.split_whitespace().collect();
+    if terms.is_empty() {
+        return Ok(SearchPage { total_hits: 0, hits: Vec::new() });
+    }
+    let mut paths = Vec::new();
+    collect_files(data_dir, true, &mut paths)?;
+
+    // (path, terms matched, match count); unreadable or non-UTF-8 files are skipped
+    let mut ranked: Vec<(PathBuf, usize, usize)> = paths
+        .into_par_iter()
+        .filter(|path| is_text_file(path))
+        .filter_map(|path| {
+            let content = fs::read_to_string(&path).ok()?;
+            let counts: Vec<usize> = terms.iter().map(|t| count_ignore_ascii_case(&content, t)).collect();
+            let matched = counts.iter().filter(|&&n| n > 0).count();
+            (matched > 0).then(|| (path, matched, counts.iter().sum()))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)).then(a.0.cmp(&b.0)));
+
+    let total_hits = ranked.len();
+    let mut hits = Vec::new();
+    for (path, terms_matched, match_count) in ranked.into_iter().skip(offset).take(limit) {
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let first = terms
+            .iter()
+            .filter_map(|t| find_ignore_ascii_case(&content, 0, t).map(|i| (i, t.len())))
+            .min();
+        let snippet = first.map(|(i, len)| snippet_around(&content, i, len)).unwrap_or_default();
+        hits.push(SearchHit {
+            path: relative_data_path(data_dir, &path)?,
+            match_count,
+            terms_matched,
+            highlights: snippet_highlights(&snippet, &terms),
+            snippet,
+        });
+    }
+    Ok(SearchPage { total_hits, hits })
+}
+
+fn count_ignore_ascii_case(haystack: &str, needle: &str) -> usize {
+    let mut count = 0;
+    let mut pos = 0;
+    while let Some(i) = find_ignore_ascii_case(haystack, pos, needle) {
+        count += 1;
+        pos = i + needle.len();
+    }
+    count
+}
+
+/// Where each term occurs in `snippet`. Overlapping matches (one term inside
+/// another) keep the earliest, longest one.
+fn snippet_highlights(snippet: &str, terms: &[&str]) -> Vec<SnippetHighlight> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for term in terms {
+        let mut pos = 0;
+        while let Some(i) = find_ignore_ascii_case(snippet, pos, term) {
+            ranges.push((i, i + term.len()));
+            pos = i + term.len();
+        }
+    }
+    ranges.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let mut highlights = Vec::new();
+    let mut covered_to = 0;
+    // Byte offsets are converted incrementally: (byte offset, UTF-16 offset) of the last conversion
+    let mut cursor = (0, 0);
+    for (start, end) in ranges {
+        if start < covered_to {
+            continue;
+        }
+        let utf16_start = cursor.1 + snippet[cursor.0..start].encode_utf16().count();
+        let length = snippet[start..end].encode_utf16().count();
+        highlights.push(SnippetHighlight { start: utf16_start, length });
+        cursor = (end, utf16_start + length);
+        covered_to = end;
+    }
+    highlights
+}
+
+/// Text around `content[start..start + len]` on char boundaries, whitespace collapsed
+fn snippet_around(content: &str, start: usize, len: usize) -> String {
+    let mut from = start.saturating_sub(SNIPPET_CONTEXT_BYTES);
+    while !content.is_char_boundary(from) {
+        from -= 1;
+    }
+    let mut to = (start + len + SNIPPET_CONTEXT_BYTES).min(content.len());
+    while !content.is_char_boundary(to) {
+        to += 1;
+    }
+    content[from..to].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Copy an EPUB into the data dir after checking that it parses
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn import_epub(app: tauri::AppHandle, source_path: String) -> Result<String, String> {
+    ensure_unlocked(&app)?;
+    let source = PathBuf::from(&source_path);
+    epub::doc::EpubDoc::new(&source)
+        .map_err(|e| format!("Not a readable EPUB '{}': {}", source_path, e))?;
+    let filename = import_filename(&source)?;
+    let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    copy_within_quota(&source, &data_dir.join(&filename), &filename)?;
+    Ok(filename)
+}
+
+#[derive(serde::Serialize)]
+struct EpubChapter {
+    id: String,
+    /// Path inside the EPUB, which relative links in `html` resolve against
+    path: String,
+    html: String,
+}
+
+#[derive(serde::Serialize)]
+struct EpubImage {
+    /// Path inside the EPUB
+    epub_path: String,
+    /// Data-dir relative path the image was extracted to
+    path: String,
+    mime_type: String,
+}
+
+#[derive(serde::Serialize)]
+struct EpubContents {
+    chapters: Vec<EpubChapter>,
+    images: Vec<EpubImage>,
+}
+
+/// Chapters in reading order plus the book's images, extracted to
+/// `<book stem>_images/` with their in-EPUB folder layout kept
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn epub_extract_chapters(app: tauri::AppHandle, filename: String) -> Result<EpubContents, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    let (root, relative) = read_root(&app, data_dir.clone(), &filename)?;
+    let source = resolve_data_path(&root, &relative)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    run_limited(&app, move || epub_extract_chapters_in(&data_dir, &source, &filename)).await
+}
+
+/// Read the book at `source`; images are written under `data_dir`
+fn epub_extract_chapters_in(data_dir: &Path, source: &Path, filename: &str) -> Result<EpubContents, String> {
+    let mut doc = epub::doc::EpubDoc::new(source)
+        .map_err(|e| format!("Failed to open EPUB '{}': {}", filename, e))?;
+
+    let mut chapters = Vec::new();
+    let spine: Vec<String> = doc.spine.iter().map(|item| item.idref.clone()).collect();
+    for id in spine {
+        let path = doc
+            .resources
+            .get(&id)
+            .map(|r| r.path.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let (html, _) = doc
+            .get_resource_str(&id)
+            .ok_or_else(|| format!("Chapter '{}' is missing from '{}'", id, filename))?;
+        chapters.push(EpubChapter { id, path, html });
+    }
+
+    let stem = Path::new(filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let images_dir = PathBuf::from(format!("{}_images", stem));
+    let mut image_items: Vec<(String, PathBuf, String)> = doc
+        .resources
+        .iter()
+        .filter(|(_, r)| r.mime.starts_with("image/"))
+        .map(|(id, r)| (id.clone(), r.path.clone(), r.mime.clone()))
+        .collect();
+    image_items.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut images = Vec::new();
+    for (id, epub_path, mime_type) in image_items {
+        // Archive paths are untrusted; never let one climb out of the book folder
+        let Some(inner) = normalize_relative_path(&epub_path) else {
+            log::warn!("[epub] Skipping image with unsafe path '{}'", epub_path.display());
+            continue;
+        };
+        let relative = images_dir.join(inner);
+        let (bytes, _) = doc
+            .get_resource(&id)
+            .ok_or_else(|| format!("Image '{}' is missing from '{}'", epub_path.display(), filename))?;
+        write_within_quota(&data_dir.join(&relative), None, &bytes, false, true, &relative.to_string_lossy())?;
+        images.push(EpubImage {
+            epub_path: epub_path.to_string_lossy().replace('\\', "/"),
+            path: relative.to_string_lossy().replace('\\', "/"),
+            mime_type,
+        });
+    }
+    Ok(EpubContents { chapters, images })
+}
+
+/// Hash used by the front-end's `AnnotationManager` to key annotation files:
+/// Java-style `h * 31 + c` over UTF-16 code units, as an unsigned 32-bit value
+fn annotation_key_hash(input: &str) -> u32 {
+    input
+        .encode_utf16()
+        .fold(0i32, |h, c| (h << 5).wrapping_sub(h).wrapping_add(c as i32)) as u32
+}
+
+/// `annotations/<stem>-<hash>.json`, mirroring `AnnotationManager`'s naming
+fn annotation_file_path(paper_id: &str) -> String {
+    let basename = paper_id.rsplit(['/', '\\']).next().unwrap_or("unknown");
+    let lower = basename.to_ascii_lowercase();
+    let stem = [".html", ".htm", ".md"]
+        .iter()
+        .find(|ext| lower.ends_with(*ext))
+        .map_or(basename, |ext| &basename[..basename.len() - ext.len()]);
+    let key = format!("{:x}", annotation_key_hash(paper_id));
+    format!("annotations/{}-{}.json", stem, &key[..key.len().min(8)])
+}
+
+/// Annotation file as the front-end stores it
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnnotationFile {
+    source_file: String,
+    annotations: Vec<serde_json::Value>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct StoredAnnotation {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    content: String,
+    target: StoredAnnotationTarget,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    created_at: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct StoredAnnotationTarget {
+    paragraph_id: String,
+    text_hash: String,
+    start_offset: u64,
+    end_offset: u64,
+    selected_text: String,
+}
+
+/// `format` value identifying an annotation export
+const ANNOTATION_EXPORT_FORMAT: &str = "peer-reviewer-annotations";
+const ANNOTATION_EXPORT_VERSION: u32 = 1;
+
+/// Portable annotation export, version 1:
+///
+/// ```json
+/// {
+///   "format": "peer-reviewer-annotations",
+///   "version": 1,
+///   "source": "papers/attention.html",
+///   "annotations": [{
+///     "id": "a1",                      // optional on import
+///     "type": "highlight",             // optional on import, defaults to "highlight"
+///     "text": "the highlighted passage",
+///     "note": "reader's comment",      // may be empty
+///     "color": "#ffeb3b",              // optional
+///     "page": null,                    // optional 1-based page for paged formats
+///     "created_at": 1700000000000,     // Unix milliseconds
+///     "location": {                    // optional anchor within this app's reader
+///       "paragraph_id": "p-12", "text_hash": "1a2b", "start_offset": 0, "end_offset": 23
+///     }
+///   }]
+/// }
+/// ```
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnnotationExport {
+    format: String,
+    version: u32,
+    source: String,
+    annotations: Vec<ExportedAnnotation>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExportedAnnotation {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    text: String,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    page: Option<u32>,
+    created_at: u64,
+    #[serde(default)]
+    location: Option<ExportedLocation>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExportedLocation {
+    paragraph_id: String,
+    #[serde(default)]
+    text_hash: String,
+    start_offset: u64,
+    end_offset: u64,
+}
+
+fn read_annotation_file(data_dir: &Path, paper_id: &str) -> Result<Option<AnnotationFile>, String> {
+    let path = data_dir.join(annotation_file_path(paper_id));
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read annotations for '{}': {}", paper_id, e))?;
+    serde_json::from_str(&raw)
+        .map(Some)
+        .map_err(|e| format!("Invalid annotation file for '{}': {}", paper_id, e))
+}
+
+/// Write a paper's annotations to `dest_path` in the portable export format
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+fn export_annotations(app: tauri::AppHandle, paper_id: String, dest_path: String) -> Result<usize, String> {
+    ensure_unlocked(&app)?;
     let data_dir = get_data_dir(&app)?;
-    let dest = data_dir.join(&filename);
-    fs::copy(&source, &dest)
-        .map_err(|e| format!("Failed to copy file: {}", e))?;
-    Ok(filename)
+    let file = read_annotation_file(&data_dir, &paper_id)?
+        .ok_or_else(|| format!("No annotations found for '{}'", paper_id))?;
+
+    let mut annotations = Vec::with_capacity(file.annotations.len());
+    for value in file.annotations {
+        let stored: StoredAnnotation = serde_json::from_value(value)
+            .map_err(|e| format!("Invalid annotation in '{}': {}", paper_id, e))?;
+        annotations.push(ExportedAnnotation {
+            id: Some(stored.id),
+            kind: Some(stored.kind),
+            text: stored.target.selected_text,
+            note: stored.content,
+            color: stored.color,
+            page: None,
+            created_at: stored.created_at,
+            location: Some(ExportedLocation {
+                paragraph_id: stored.target.paragraph_id,
+                text_hash: stored.target.text_hash,
+                start_offset: stored.target.start_offset,
+                end_offset: stored.target.end_offset,
+            }),
+        });
+    }
+    let count = annotations.len();
+    let export = AnnotationExport {
+        format: ANNOTATION_EXPORT_FORMAT.to_string(),
+        version: ANNOTATION_EXPORT_VERSION,
+        source: file.source_file,
+        annotations,
+    };
+    let json = serde_json::to_string_pretty(&export)
+        .map_err(|e| format!("Failed to serialize annotations: {}", e))?;
+    atomic_write(Path::new(&dest_path), json.as_bytes())
+        .map_err(|e| format!("Failed to write '{}': {}", dest_path, e))?;
+    Ok(count)
 }
 
+/// Merge an annotation export into a paper's annotations. The whole file is
+/// validated before anything is written; annotations whose id already exists
+/// are skipped. Returns how many were added.
 #[tauri::command]
-fn read_data_file(app: tauri::AppHandle, filename: String) -> Result<String, String> {
+#[tracing::instrument(skip(app), err)]
+fn import_annotations(app: tauri::AppHandle, paper_id: String, source_path: String) -> Result<usize, String> {
+    ensure_unlocked(&app)?;
     let data_dir = get_data_dir(&app)?;
-    let path = data_dir.join(&filename);
-    fs::read_to_string(&path)
-        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let raw = fs::read_to_string(&source_path)
+        .map_err(|e| format!("Failed to read '{}': {}", source_path, e))?;
+    let export: AnnotationExport = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid annotation export: {}", e))?;
+    if export.format != ANNOTATION_EXPORT_FORMAT {
+        return Err(format!("Unknown annotation format '{}'", export.format));
+    }
+    if export.version != ANNOTATION_EXPORT_VERSION {
+        return Err(format!("Unsupported annotation export version {}", export.version));
+    }
+    for (i, annotation) in export.annotations.iter().enumerate() {
+        if annotation.text.trim().is_empty() {
+            return Err(format!("Annotation {} has no text", i));
+        }
+        if let Some(location) = &annotation.location {
+            if location.start_offset > location.end_offset {
+                return Err(format!("Annotation {} has start_offset after end_offset", i));
+            }
+        }
+    }
+
+    let mut file = read_annotation_file(&data_dir, &paper_id)?.unwrap_or(AnnotationFile {
+        source_file: paper_id.clone(),
+        annotations: Vec::new(),
+    });
+    let mut existing: std::collections::HashSet<String> = file
+        .annotations
+        .iter()
+        .filter_map(|a| a.get("id").and_then(|id| id.as_str()).map(str::to_string))
+        .collect();
+    let mut added = 0;
+    for (i, annotation) in export.annotations.into_iter().enumerate() {
+        let id = annotation
+            .id
+            .unwrap_or_else(|| format!("import-{}-{}", now_millis(), i));
+        if !existing.insert(id.clone()) {
+            continue;
+        }
+        let location = annotation.location.unwrap_or(ExportedLocation {
+            paragraph_id: String::new(),
+            text_hash: String::new(),
+            start_offset: 0,
+            end_offset: 0,
+        });
+        let stored = StoredAnnotation {
+            id,
+            kind: annotation.kind.unwrap_or_else(|| "highlight".to_string()),
+            content: annotation.note,
+            target: StoredAnnotationTarget {
+                paragraph_id: location.paragraph_id,
+                text_hash: location.text_hash,
+                start_offset: location.start_offset,
+                end_offset: location.end_offset,
+                selected_text: annotation.text,
+            },
+            color: annotation.color,
+            created_at: annotation.created_at,
+        };
+        file.annotations.push(
+            serde_json::to_value(stored).map_err(|e| format!("Failed to serialize annotation: {}", e))?,
+        );
+        added += 1;
+    }
+
+    let json = serde_json::to_string_pretty(&file)
+        .map_err(|e| format!("Failed to serialize annotations: {}", e))?;
+    let annotations = annotation_file_path(&paper_id);
+    write_within_quota(&data_dir.join(&annotations), None, json.as_bytes(), false, true, &annotations)?;
+    Ok(added)
+}
+
+/// Session reports written by `generate_session_report`
+const SESSION_REPORT_DIR: &str = "reports";
+
+/// Note extensions whose words count toward "words written"
+const NOTE_EXTENSIONS: &[&str] = &["md", "markdown", "txt"];
+
+/// `YYYY-MM-DD HH:MM UTC` for a Unix millisecond timestamp
+fn format_utc_millis(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil-from-days (Howard Hinnant), days counted from 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60
+    )
+}
+
+/// Markdown summary of activity since `since`: files modified, papers read,
+/// annotations added, and words written. Words are counted over notes
+/// modified in the window plus the text of new annotations, since the data
+/// dir keeps no edit history.
+fn session_report_in(data_dir: &Path, since: u64) -> Result<String, String> {
+    let mut files = Vec::new();
+    collect_files(data_dir, true, &mut files)?;
+
+    let mut modified = Vec::new();
+    let mut words = 0;
+    let mut annotated = Vec::new();
+    for path in &files {
+        let rel = relative_data_path(data_dir, path)?;
+        if rel.starts_with(&format!("{}/", SESSION_REPORT_DIR)) {
+            continue;
+        }
+        if rel.starts_with("annotations/") {
+            let Ok(file) = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|raw| serde_json::from_str::<AnnotationFile>(&raw).map_err(|e| e.to_string()))
+            else {
+                log::warn!("Skipping unreadable annotation file '{}'", rel);
+                continue;
+            };
+            let mut added = 0;
+            for value in file.annotations {
+                let Ok(stored) = serde_json::from_value::<StoredAnnotation>(value) else {
+                    continue;
+                };
+                if stored.created_at >= since {
+                    added += 1;
+                    words += stored.content.split_whitespace().count();
+                }
+            }
+            if added > 0 {
+                annotated.push((file.source_file, added));
+            }
+            continue;
+        }
+        let metadata = fs::metadata(path).map_err(|e| format!("Failed to stat '{}': {}", rel, e))?;
+        let modified_at = modified_millis(&metadata);
+        if modified_at < since {
+            continue;
+        }
+        let is_note = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| NOTE_EXTENSIONS.contains(&ext.as_str()));
+        if is_note {
+            let content = fs::read(path).map_err(|e| format!("Failed to read '{}': {}", rel, e))?;
+            words += String::from_utf8_lossy(&content).split_whitespace().count();
+        }
+        modified.push((rel, modified_at));
+    }
+    modified.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    annotated.sort();
+
+    let conn = open_index(data_dir)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT files.path, page FROM reading_progress JOIN files ON files.id = reading_progress.file_id
+             WHERE updated_at >= ?1 ORDER BY updated_at DESC",
+        )
+        .map_err(|e| format!("Failed to query reading progress: {}", e))?;
+    let read: Vec<(String, u32)> = stmt
+        .query_map([since as i64], |row| Ok((row.get(0)?, row.get(1)?)))
+        .and_then(|rows| rows.collect())
+        .map_err(|e| format!("Failed to query reading progress: {}", e))?;
+
+    let annotation_count: usize = annotated.iter().map(|(_, n)| n).sum();
+    let mut report = format!(
+        "# Session report\n\n{} to {}\n\n- Files modified: {}\n- Papers read: {}\n- Annotations added: {}\n- Words written: {}\n",
+        format_utc_millis(since),
+        format_utc_millis(now_millis()),
+        modified.len(),
+        read.len(),
+        annotation_count,
+        words
+    );
+    if !modified.is_empty() {
+        report.push_str("\n## Files modified\n\n");
+        for (rel, modified_at) in &modified {
+            report.push_str(&format!("- `{}` ({})\n", rel, format_utc_millis(*modified_at)));
+        }
+    }
+    if !read.is_empty() {
+        report.push_str("\n## Papers read\n\n");
+        for (paper_id, page) in &read {
+            report.push_str(&format!("- `{}` (page {})\n", paper_id, page));
+        }
+    }
+    if !annotated.is_empty() {
+        report.push_str("\n## Annotations added\n\n");
+        for (source, added) in &annotated {
+            report.push_str(&format!("- `{}`: {}\n", source, added));
+        }
+    }
+    Ok(report)
 }
 
+/// Write a markdown activity report covering everything since `since_timestamp`
+/// (Unix milliseconds) to `reports/` and return its data-dir relative filename
 #[tauri::command]
-fn read_data_file_binary(app: tauri::AppHandle, filename: String) -> Result<String, String> {
+#[tracing::instrument(skip(app), err)]
+fn generate_session_report(app: tauri::AppHandle, since_timestamp: u64) -> Result<String, String> {
+    ensure_unlocked(&app)?;
     let data_dir = get_data_dir(&app)?;
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let report = session_report_in(&data_dir, since_timestamp)?;
+    let filename = format!("{}/session-{}.md", SESSION_REPORT_DIR, now_millis());
     let path = data_dir.join(&filename);
-    let bytes = fs::read(&path)
-        .map_err(|e| format!("Failed to read binary file '{}': {}", filename, e))?;
-    Ok(general_purpose::STANDARD.encode(&bytes))
+    write_within_quota(&path, None, report.as_bytes(), false, true, &filename)?;
+    Ok(filename)
+}
+
+/// Log of renames made by `fix_mojibake`, kept so they can be undone
+const MOJIBAKE_LOG_FILE: &str = ".mojibake_renames.json";
+
+/// Undo the classic double encoding: UTF-8 bytes that were decoded as
+/// Windows-1252 and saved as UTF-8 again ("ë…¼ë¬¸" for "논문"). Returns the
+/// repaired name only when it differs and is valid UTF-8.
+fn repair_mojibake(name: &str) -> Option<String> {
+    if name.is_ascii() {
+        return None;
+    }
+    let (bytes, _, had_errors) = encoding_rs::WINDOWS_1252.encode(name);
+    if had_errors {
+        return None;
+    }
+    // Genuine Latin-1 names ("café") give bytes that aren't valid UTF-8, so they fail here
+    let repaired = std::str::from_utf8(&bytes).ok()?;
+    (repaired != name && !repaired.is_ascii()).then(|| repaired.to_string())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MojibakeRename {
+    from: String,
+    to: String,
+}
+
+#[derive(serde::Serialize)]
+struct FailedRename {
+    filename: String,
+    error: String,
+}
+
+#[derive(serde::Serialize)]
+struct MojibakeFixResult {
+    renamed: Vec<MojibakeRename>,
+    failed: Vec<FailedRename>,
 }
 
+/// Files whose names look double-encoded, with the name each would be fixed to
 #[tauri::command]
-fn write_data_file(app: tauri::AppHandle, filename: String, content: String) -> Result<(), String> {
+#[tracing::instrument(skip(app), err)]
+fn detect_mojibake(app: tauri::AppHandle) -> Result<Vec<MojibakeRename>, String> {
+    ensure_unlocked(&app)?;
     let data_dir = get_data_dir(&app)?;
-    let path = data_dir.join(&filename);
-    // Ensure subdirectories exist
-    if let Some(parent) = path.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create directory: {}", e))?;
+    let mut paths = Vec::new();
+    collect_files(&data_dir, true, &mut paths)?;
+    let mut found = Vec::new();
+    for path in paths {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if let Some(repaired) = repair_mojibake(&name) {
+            let from = relative_data_path(&data_dir, &path)?;
+            let to = relative_data_path(&data_dir, &path.with_file_name(repaired))?;
+            found.push(MojibakeRename { from, to });
         }
     }
-    fs::write(&path, &content)
-        .map_err(|e| format!("Failed to write file '{}': {}", filename, e))
+    Ok(found)
 }
 
+/// Rename the given files to their repaired names. Each rename is appended to
+/// `.mojibake_renames.json` before it happens, so the log is never missing one.
 #[tauri::command]
-fn list_data_files(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+#[tracing::instrument(skip(app), err)]
+fn fix_mojibake(app: tauri::AppHandle, filenames: Vec<String>) -> Result<MojibakeFixResult, String> {
+    ensure_unlocked(&app)?;
     let data_dir = get_data_dir(&app)?;
-    let mut files = Vec::new();
-    fn walk(dir: &PathBuf, base: &PathBuf, files: &mut Vec<String>) -> Result<(), String> {
-        let entries = fs::read_dir(dir)
-            .map_err(|e| format!("Failed to read directory: {}", e))?;
-        for entry in entries {
-            let entry = entry.map_err(|e| format!("Dir entry error: {}", e))?;
-            let path = entry.path();
-            if path.is_dir() {
-                walk(&path, base, files)?;
-            } else if path.is_file() {
-                let rel = path.strip_prefix(base)
-                    .map_err(|e| format!("Path error: {}", e))?;
-                files.push(rel.to_string_lossy().replace('\\', "/"));
+    let _pending = app.state::<PendingWrites>().inner().begin();
+    let log_path = data_dir.join(MOJIBAKE_LOG_FILE);
+    let mut log: Vec<MojibakeRename> = match fs::read_to_string(&log_path) {
+        Ok(raw) => serde_json::from_str(&raw).map_err(|e| format!("Invalid rename log: {}", e))?,
+        Err(_) => Vec::new(),
+    };
+
+    let mut result = MojibakeFixResult { renamed: Vec::new(), failed: Vec::new() };
+    for filename in filenames {
+        let rename = || -> Result<MojibakeRename, String> {
+            let path = resolve_data_path(&data_dir, &filename)?;
+            if !path.is_file() {
+                return Err("File does not exist".to_string());
+            }
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let repaired = repair_mojibake(&name).ok_or("Name does not look double-encoded")?;
+            let target = path.with_file_name(&repaired);
+            if target.exists() {
+                return Err(format!("'{}' already exists", repaired));
+            }
+            Ok(MojibakeRename { from: filename.clone(), to: relative_data_path(&data_dir, &target)? })
+        };
+        let entry = match rename() {
+            Ok(entry) => entry,
+            Err(error) => {
+                result.failed.push(FailedRename { filename, error });
+                continue;
+            }
+        };
+
+        log.push(MojibakeRename { from: entry.from.clone(), to: entry.to.clone() });
+        let json = serde_json::to_string_pretty(&log)
+            .map_err(|e| format!("Failed to serialize rename log: {}", e))?;
+        atomic_write(&log_path, json.as_bytes())
+            .map_err(|e| format!("Failed to write rename log: {}", e))?;
+        match fs::rename(data_dir.join(&entry.from), data_dir.join(&entry.to)) {
+            Ok(()) => {
+                if let Err(e) = record_file_rename(&data_dir, &entry.from, &entry.to) {
+                    log::warn!("[fix_mojibake] {}", e);
+                }
+                result.renamed.push(entry);
+            }
+            Err(e) => {
+                log.pop();
+                result.failed.push(FailedRename { filename, error: format!("Failed to rename: {}", e) });
             }
         }
-        Ok(())
     }
-    walk(&data_dir, &data_dir, &mut files)?;
-    Ok(files)
+    if !result.renamed.is_empty() {
+        app.state::<ListCache>().invalidate();
+    }
+    // Drop log entries for renames that failed after being logged
+    let json = serde_json::to_string_pretty(&log)
+        .map_err(|e| format!("Failed to serialize rename log: {}", e))?;
+    atomic_write(&log_path, json.as_bytes())
+        .map_err(|e| format!("Failed to write rename log: {}", e))?;
+    Ok(result)
+}
+
+const PREFERENCES_STORE_FILE: &str = "preferences.json";
+
+/// User preferences (last-opened directory, zoom, theme) in the app config dir,
+/// separate from the data dir so they survive a library reset
+fn preferences_store(
+    app: &tauri::AppHandle,
+) -> Result<std::sync::Arc<tauri_plugin_store::Store<tauri::Wry>>, String> {
+    use tauri_plugin_store::StoreExt;
+
+    let config_dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| format!("Failed to get app config dir: {}", e))?;
+    app.store(config_dir.join(PREFERENCES_STORE_FILE))
+        .map_err(|e| format!("Failed to open preferences: {}", e))
 }
 
 #[tauri::command]
-fn check_data_file_exists(app: tauri::AppHandle, filename: String) -> Result<bool, String> {
-    let data_dir = get_data_dir(&app)?;
-    Ok(data_dir.join(&filename).exists())
+#[tracing::instrument(skip(app), err)]
+fn get_preference(app: tauri::AppHandle, key: String) -> Result<Option<serde_json::Value>, String> {
+    ensure_unlocked(&app)?;
+    Ok(preferences_store(&app)?.get(&key))
 }
 
 #[tauri::command]
-fn delete_data_file(app: tauri::AppHandle, filename: String) -> Result<(), String> {
-    let data_dir = get_data_dir(&app)?;
-    let path = data_dir.join(&filename);
-    if path.exists() {
-        fs::remove_file(&path)
-            .map_err(|e| format!("Failed to delete file: {}", e))?;
+#[tracing::instrument(skip(app, value), err)]
+fn set_preference(app: tauri::AppHandle, key: String, value: serde_json::Value) -> Result<(), String> {
+    ensure_unlocked(&app)?;
+    let store = preferences_store(&app)?;
+    store.set(key, value);
+    store.save().map_err(|e| format!("Failed to save preferences: {}", e))
+}
+
+const WINDOW_STATE_FILE: &str = ".window_state.json";
+
+/// Window geometry in physical pixels, saved on close and restored at startup
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WindowState {
+    width: f64,
+    height: f64,
+    x: f64,
+    y: f64,
+    maximized: bool,
+}
+
+fn save_window_state(window: &tauri::Window) -> Result<(), String> {
+    let path = get_data_dir(window.app_handle())?.join(WINDOW_STATE_FILE);
+    let maximized = window.is_maximized().unwrap_or(false);
+    let previous = fs::read_to_string(&path)
+        .ok()
+        .and_then(|raw| serde_json::from_str::<WindowState>(&raw).ok());
+
+    let state = match previous {
+        // A maximized window reports the screen size; keep the last normal geometry
+        Some(prev) if maximized => WindowState { maximized, ..prev },
+        _ => {
+            let size = window.inner_size().map_err(|e| format!("Failed to get window size: {}", e))?;
+            let position = window.outer_position().map_err(|e| format!("Failed to get window position: {}", e))?;
+            WindowState {
+                width: size.width as f64,
+                height: size.height as f64,
+                x: position.x as f64,
+                y: position.y as f64,
+                maximized,
+            }
+        }
+    };
+    let json = serde_json::to_string_pretty(&state)
+        .map_err(|e| format!("Failed to serialize window state: {}", e))?;
+    atomic_write(&path, json.as_bytes())
+        .map_err(|e| format!("Failed to write window state: {}", e))
+}
+
+fn restore_window_state(app: &tauri::AppHandle) -> Result<(), String> {
+    let path = get_data_dir(app)?.join(WINDOW_STATE_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read window state: {}", e))?;
+    let state: WindowState = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid window state: {}", e))?;
+    let window = app.get_webview_window("main").ok_or("Main window not found")?;
+
+    window
+        .set_size(tauri::PhysicalSize::new(state.width as u32, state.height as u32))
+        .map_err(|e| format!("Failed to restore window size: {}", e))?;
+    window
+        .set_position(tauri::PhysicalPosition::new(state.x as i32, state.y as i32))
+        .map_err(|e| format!("Failed to restore window position: {}", e))?;
+    if state.maximized {
+        window.maximize().map_err(|e| format!("Failed to maximize window: {}", e))?;
     }
     Ok(())
 }
 
-#[tauri::command]
-fn get_data_dir_path(app: tauri::AppHandle) -> Result<String, String> {
-    let data_dir = get_data_dir(&app)?;
-    Ok(data_dir.to_string_lossy().to_string())
+/// File menu entries as (menu id, label, event emitted to the front-end)
+#[cfg(not(target_os = "android"))]
+const FILE_MENU_ITEMS: &[(&str, &str, &str)] = &[
+    ("open-paper", "Open Paper...", "menu:open-paper"),
+    ("export-library", "Export Library", "menu:export-library"),
+    ("preferences", "Preferences", "menu:preferences"),
+];
+
+/// Native menu bar. The Edit submenu keeps the standard clipboard shortcuts
+/// working on macOS, where they are tied to menu items.
+#[cfg(not(target_os = "android"))]
+fn setup_menu(app: &tauri::App) -> tauri::Result<()> {
+    use tauri::menu::{MenuBuilder, MenuItemBuilder, SubmenuBuilder};
+    use tauri::Emitter;
+
+    let mut file = SubmenuBuilder::new(app, "File");
+    for (id, label, _) in FILE_MENU_ITEMS {
+        file = file.item(&MenuItemBuilder::with_id(*id, *label).build(app)?);
+    }
+    let edit = SubmenuBuilder::new(app, "Edit")
+        .undo()
+        .redo()
+        .separator()
+        .cut()
+        .copy()
+        .paste()
+        .select_all()
+        .build()?;
+    let menu = MenuBuilder::new(app).item(&file.build()?).item(&edit).build()?;
+    app.set_menu(menu)?;
+
+    app.on_menu_event(|app, event| {
+        let Some((_, _, name)) = FILE_MENU_ITEMS.iter().find(|(id, _, _)| event.id() == *id) else {
+            return;
+        };
+        if let Err(e) = app.emit(name, ()) {
+            log::warn!("[menu] Failed to emit {}: {}", name, e);
+        }
+    });
+    Ok(())
 }
 
-/// Copy an HTML file to data dir along with any images referenced via <img src="...">
-#[tauri::command]
-fn copy_html_with_images(app: tauri::AppHandle, source_path: String) -> Result<String, String> {
-    let source = PathBuf::from(&source_path);
-    if !source.exists() {
-        return Err(format!("Source file does not exist: {}", source_path));
+/// Open the system file manager at a data file (selected, where supported) or
+/// directory. An empty `filename` opens the data directory itself. Used by the
+/// tray only; it is not exposed as a command.
+fn reveal_data_file_in_explorer(app: &tauri::AppHandle, filename: &str) -> Result<(), String> {
+    let data_dir = get_data_dir(app)?;
+    let path = resolve_data_path(&data_dir, filename)?;
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", filename));
     }
-    let source_dir = source.parent().unwrap_or_else(|| std::path::Path::new("."));
-    let filename = source
-        .file_name()
-        .ok_or("Invalid filename")?
-        .to_string_lossy()
-        .to_string();
-    let data_dir = get_data_dir(&app)?;
+    let mut command;
+    #[cfg(target_os = "windows")]
+    {
+        // explorer.exe doesn't parse a quoted "/select,..." argument, which is what
+        // `arg` produces for paths with spaces, so pass it through verbatim
+        use std::os::windows::process::CommandExt;
+        command = std::process::Command::new("explorer");
+        if path.is_file() {
+            command.raw_arg(format!("/select,\"{}\"", path.display()));
+        } else {
+            command.arg(&path);
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    if cfg!(target_os = "macos") {
+        command = std::process::Command::new("open");
+        if path.is_file() {
+            command.arg("-R");
+        }
+        command.arg(&path);
+    } else {
+        // xdg-open can't select a file, so open its folder
+        command = std::process::Command::new("xdg-open");
+        command.arg(if path.is_file() { path.parent().unwrap_or(&path) } else { &path });
+    }
+    command
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Failed to open file manager: {}", e))
+}
 
-    // Copy the HTML file itself
-    let dest = data_dir.join(&filename);
-    fs::copy(&source, &dest)
-        .map_err(|e| format!("Failed to copy HTML file: {}", e))?;
+#[derive(serde::Serialize)]
+struct SecureDirReport {
+    /// False where the platform has no supported mechanism; nothing was changed
+    supported: bool,
+    /// Data-dir relative paths whose permissions were tightened (`.` is the data dir)
+    changed: Vec<String>,
+    message: String,
+}
 
-    // Read HTML content and extract image references
-    let content = fs::read_to_string(&source)
-        .unwrap_or_default();
+/// Make the data dir private to the current user. On Unix, group and other
+/// permission bits are cleared on the data dir and everything in it (directories
+/// end up 700, files keep their owner bits, e.g. 600). On Windows, inherited
+/// permissions are removed and the current user alone is granted full control,
+/// recursively via `icacls`. Symlinks are left alone. Elsewhere this changes nothing.
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+async fn secure_data_dir(app: tauri::AppHandle) -> Result<SecureDirReport, String> {
+    ensure_unlocked(&app)?;
+    let data_dir = get_data_dir(&app)?;
+    run_limited(&app, move || secure_data_dir_in(&data_dir)).await
+}
 
-    // Simple regex-like scan for src="..." in img tags
-    // We look for patterns like src="relative/path.png" (not http/data/blob URLs)
-    let mut pos = 0;
-    let content_bytes = content.as_bytes();
-    let content_len = content_bytes.len();
-    let mut copied_count = 0u32;
-
-    while pos < content_len {
-        // Find <img (case insensitive)
-        if let Some(img_pos) = content[pos..].to_lowercase().find("<img") {
-            let abs_pos = pos + img_pos;
-            // Find src= within the next 1000 chars
-            let search_end = (abs_pos + 1000).min(content_len);
-            let tag_region = &content[abs_pos..search_end];
-
-            // Find src attribute
-            if let Some(src_offset) = tag_region.to_lowercase().find("src=") {
-                let src_start = src_offset + 4; // skip "src="
-                if src_start < tag_region.len() {
-                    let quote_char = tag_region.as_bytes()[src_start];
-                    if quote_char == b'"' || quote_char == b'\'' {
-                        let value_start = src_start + 1;
-                        if let Some(end_quote) = tag_region[value_start..].find(quote_char as char) {
-                            let src_value = &tag_region[value_start..value_start + end_quote];
-
-                            // Skip absolute URLs, data URIs, blob URIs
-                            let trimmed = src_value.trim();
-                            if !trimmed.is_empty()
-                                && !trimmed.starts_with("http://")
-                                && !trimmed.starts_with("https://")
-                                && !trimmed.starts_with("data:")
-                                && !trimmed.starts_with("blob:")
-                                && !trimmed.starts_with("file:")
-                                && !trimmed.starts_with('/')
-                            {
-                                // Decode URL encoding
-                                let decoded = urlencoding_decode(trimmed);
-                                let img_source = source_dir.join(&decoded);
-                                if img_source.exists() {
-                                    let img_dest = data_dir.join(&decoded);
-                                    // Create subdirectories if needed
-                                    if let Some(parent) = img_dest.parent() {
-                                        if !parent.exists() {
-                                            let _ = fs::create_dir_all(parent);
-                                        }
-                                    }
-                                    if fs::copy(&img_source, &img_dest).is_ok() {
-                                        copied_count += 1;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+#[cfg(unix)]
+fn secure_data_dir_in(data_dir: &Path) -> Result<SecureDirReport, String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fn walk(path: &Path, data_dir: &Path, changed: &mut Vec<String>) -> Result<(), String> {
+        let metadata = fs::symlink_metadata(path)
+            .map_err(|e| format!("Failed to stat '{}': {}", path.display(), e))?;
+        if metadata.file_type().is_symlink() {
+            return Ok(());
+        }
+        let mode = metadata.permissions().mode() & 0o7777;
+        let private = if metadata.is_dir() { (mode & !0o077) | 0o700 } else { mode & !0o077 };
+        if private != mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(private))
+                .map_err(|e| format!("Failed to set permissions on '{}': {}", path.display(), e))?;
+            let rel = relative_data_path(data_dir, path)?;
+            changed.push(if rel.is_empty() { ".".to_string() } else { rel });
+        }
+        if metadata.is_dir() {
+            let entries = fs::read_dir(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+            for entry in entries {
+                let entry = entry.map_err(|e| format!("Dir entry error: {}", e))?;
+                walk(&entry.path(), data_dir, changed)?;
             }
-            pos = abs_pos + 4;
-        } else {
-            break;
         }
+        Ok(())
     }
 
-    println!("[copy_html_with_images] Copied {} images alongside '{}'", copied_count, filename);
-    Ok(filename)
+    let mut changed = Vec::new();
+    walk(data_dir, data_dir, &mut changed)?;
+    let message = format!("Removed group and other access from {} entries", changed.len());
+    Ok(SecureDirReport { supported: true, changed, message })
 }
 
-/// Simple URL decoding (handles %XX sequences)
-fn urlencoding_decode(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let bytes = input.as_bytes();
-    let mut i = 0;
-    while i < bytes.len() {
-        if bytes[i] == b'%' && i + 2 < bytes.len() {
-            if let Ok(val) = u8::from_str_radix(
-                &input[i + 1..i + 3],
-                16,
-            ) {
-                result.push(val as char);
-                i += 3;
-                continue;
+#[cfg(windows)]
+fn secure_data_dir_in(data_dir: &Path) -> Result<SecureDirReport, String> {
+    let user = std::env::var("USERNAME").map_err(|e| format!("Failed to determine the current user: {}", e))?;
+    let output = std::process::Command::new("icacls")
+        .arg(data_dir)
+        .args(["/inheritance:r", "/grant:r", &format!("{}:(OI)(CI)F", user), "/T", "/C", "/Q"])
+        .output()
+        .map_err(|e| format!("Failed to run icacls: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "icacls failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(SecureDirReport {
+        supported: true,
+        changed: vec![".".to_string()],
+        message: format!("Restricted access to {} (applied to all contents)", user),
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn secure_data_dir_in(_data_dir: &Path) -> Result<SecureDirReport, String> {
+    Ok(SecureDirReport {
+        supported: false,
+        changed: Vec::new(),
+        message: "Securing the data directory isn't supported on this platform".to_string(),
+    })
+}
+
+/// Tray menu entries as (menu id, label)
+#[cfg(desktop)]
+const TRAY_MENU_ITEMS: &[(&str, &str)] = &[
+    ("tray-open", "Open"),
+    ("tray-show-data-dir", "Show Data Dir"),
+    ("tray-quit", "Quit"),
+];
+
+/// URL scheme (registered in tauri.conf.json) for `paperreader://open?path=<file>` links
+const DEEP_LINK_SCHEME: &str = "paperreader";
+
+/// Paper types a deep link may import; any page can open a link, so nothing
+/// else (keys, dotfiles, ...) gets copied into the data dir that way
+const DEEP_LINK_EXTENSIONS: &[&str] = &["pdf", "epub", "html", "htm", "md", "markdown", "txt"];
+
+#[derive(Clone, serde::Serialize)]
+struct DeepLinkOpenEvent {
+    filename: String,
+}
+
+/// Import the file a `paperreader://open?path=...` link names (reusing an earlier
+/// import of the same file) and emit `deep-link-open` with its data-dir filename
+fn handle_deep_link(app: &tauri::AppHandle, url: &tauri::Url) -> Result<(), String> {
+    use tauri::Emitter;
+
+    if url.scheme() != DEEP_LINK_SCHEME || url.host_str() != Some("open") {
+        return Err(format!("Unsupported link: {}", url));
+    }
+    let path = url
+        .query_pairs()
+        .find(|(key, _)| key == "path")
+        .map(|(_, value)| value.into_owned())
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| format!("Link has no path: {}", url))?;
+    let is_paper = Path::new(&path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| DEEP_LINK_EXTENSIONS.contains(&ext.as_str()));
+    if !is_paper {
+        return Err(format!("Link does not name a paper file: {}", path));
+    }
+    let copied = copy_file_to_data(app.clone(), path, None)?;
+    app.emit("deep-link-open", DeepLinkOpenEvent { filename: copied.filename })
+        .map_err(|e| format!("Failed to emit deep-link-open: {}", e))
+}
+
+/// Handle links opened while the app runs (macOS) and the one it was launched
+/// with (Windows passes it on the command line)
+#[cfg(desktop)]
+fn setup_deep_links(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
+    use tauri_plugin_deep_link::DeepLinkExt;
+
+    let handle = app.handle().clone();
+    app.deep_link().on_open_url(move |event| {
+        for url in event.urls() {
+            if let Err(e) = handle_deep_link(&handle, &url) {
+                log::warn!("[deep_link] {}", e);
             }
         }
-        result.push(bytes[i] as char);
-        i += 1;
+    });
+    for url in app.deep_link().get_current()?.unwrap_or_default() {
+        if let Err(e) = handle_deep_link(app.handle(), &url) {
+            log::warn!("[deep_link] {}", e);
+        }
     }
-    result
+    Ok(())
+}
+
+/// System-tray icon with Open / Show Data Dir / Quit
+#[cfg(desktop)]
+fn setup_tray(app: &tauri::App) -> tauri::Result<()> {
+    use tauri::menu::{MenuBuilder, MenuItemBuilder};
+    use tauri::tray::TrayIconBuilder;
+
+    let mut menu = MenuBuilder::new(app);
+    for (id, label) in TRAY_MENU_ITEMS {
+        menu = menu.item(&MenuItemBuilder::with_id(*id, *label).build(app)?);
+    }
+    let mut tray = TrayIconBuilder::with_id("main")
+        .tooltip("Peer Reviewer")
+        .menu(&menu.build()?)
+        .on_menu_event(|app, event| match event.id().as_ref() {
+            "tray-open" => {
+                let Some(window) = app.get_webview_window("main") else {
+                    return;
+                };
+                let shown = window.show().and_then(|_| window.unminimize()).and_then(|_| window.set_focus());
+                if let Err(e) = shown {
+                    log::warn!("[tray] Failed to show main window: {}", e);
+                }
+            }
+            "tray-show-data-dir" => {
+                if let Err(e) = reveal_data_file_in_explorer(app, "") {
+                    log::warn!("[tray] {}", e);
+                }
+            }
+            "tray-quit" => app.exit(0),
+            _ => {}
+        });
+    if let Some(icon) = app.default_window_icon() {
+        tray = tray.icon(icon.clone());
+    }
+    tray.build(app)?;
+    Ok(())
 }
 
 use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log::error!("[panic] {}", info);
+        default_panic_hook(info);
+    }));
+
     tauri::Builder::default()
+        .manage(PendingWrites::default())
+        .manage(OpenFiles::default())
+        .manage(FileFollowers::default())
+        .manage(VfsOverlays::default())
+        .manage(VersionedWrites::default())
+        .manage(ListCache::default())
+        .manage(DataLock::default())
+        .manage(JobLimiter::default())
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_store::Builder::default().build())
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_fs::init())
         .invoke_handler(tauri::generate_handler![
             copy_file_to_data,
+            import_url_list,
+            resolve_doi,
+            read_file_type_tag,
+            get_file_tags,
+            set_file_tags,
+            find_files_by_tag,
             copy_html_with_images,
             read_data_file,
+            read_data_file_lines,
             read_data_file_binary,
+            hexdump_data_file,
+            clear_binary_cache,
+            evict_binary_cache_entry,
+            read_data_file_with_hash,
+            normalized_content_hash,
+            find_near_duplicate_notes,
+            find_similar_images,
+            verify_all_asset_links,
+            longest_common_passage,
             write_data_file,
+            sanitize_filename,
+            write_data_file_binary,
+            write_data_file_tidy,
+            get_data_file_version,
+            write_data_file_versioned,
+            anonymize_note,
+            extract_note_data_uris,
+            format_json_note,
             list_data_files,
+            list_data_files_sorted,
+            list_data_files_cached,
+            invalidate_list_cache,
+            set_list_cache_ttl,
+            add_vfs_overlay,
+            export_tree_outline,
+            list_data_files_with_metadata,
+            get_data_file_metadata,
+            rebuild_index,
             check_data_file_exists,
             delete_data_file,
+            mark_file_open,
+            mark_file_closed,
+            batch_delete_data_files,
+            swap_data_files,
             get_data_dir_path,
+            get_data_dir_asset_url,
+            set_master_passphrase,
+            unlock_data,
+            lock_data,
+            extract_note_headings,
+            extract_figure_captions,
+            extract_footnotes,
+            migrate_data,
+            compact_search_index,
+            set_reading_progress,
+            get_reading_progress,
+            list_changed_since,
+            get_folder_stats,
+            compute_data_tree_hashes,
+            pdf_export_page_image,
+            extract_image_palette,
+            scan_encoding_issues,
+            reencode_to_utf8,
+            store_blob,
+            read_blob,
+            blob_exists,
+            gc_blobs,
+            set_max_concurrent_jobs,
+            search_data_files,
+            import_epub,
+            epub_extract_chapters,
+            export_annotations,
+            import_annotations,
+            detect_mojibake,
+            fix_mojibake,
+            generate_session_report,
+            pdf_check_pdfa,
+            set_directory_quota,
+            pregenerate_thumbnails,
+            stream_data_file_lines,
+            follow_data_file,
+            stop_following,
+            get_image_dimensions,
+            pin_file,
+            unpin_file,
+            list_pinned,
+            set_paper_summary,
+            get_paper_summary,
+            secure_data_dir,
+            get_preference,
+            set_preference,
+            get_error_log,
         ])
         .setup(|app| {
             if cfg!(debug_assertions) {
@@ -250,8 +7614,410 @@ pub fn run() {
                         .build(),
                 )?;
             }
+            let _ = APP_HANDLE.set(app.handle().clone());
+            let data_dir = get_data_dir(app.handle())?;
+            if let Err(e) = init_tracing(app.handle(), &data_dir) {
+                log::warn!("[tracing] {}", e);
+            }
+            app.manage(BinaryCache::new(BINARY_CACHE_ENTRIES, BINARY_CACHE_MAX_BYTES));
+            preferences_store(app.handle())?;
+            if let Err(e) = run_migrations(&data_dir) {
+                log::error!("[migrate_data] {}", e);
+            }
+            if let Err(e) = restore_window_state(app.handle()) {
+                log::warn!("[window_state] {}", e);
+            }
+            #[cfg(not(target_os = "android"))]
+            setup_menu(app)?;
+            #[cfg(desktop)]
+            setup_tray(app)?;
+            #[cfg(desktop)]
+            if let Err(e) = setup_deep_links(app) {
+                log::error!("[deep_link] {}", e);
+            }
+            // Ctrl-C / SIGTERM bypass the window close path, so drain writes here too
+            let handle = app.handle().clone();
+            if let Err(e) = ctrlc::set_handler(move || {
+                flush_pending_writes(&handle);
+                handle.exit(0);
+            }) {
+                log::warn!("[shutdown] Failed to install signal handler: {}", e);
+            }
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { .. } = event {
+                if let Err(e) = save_window_state(window) {
+                    log::warn!("[window_state] {}", e);
+                }
+            }
+        })
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                flush_pending_writes(app);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn write_then_read_round_trips(
+            filename in "[A-Za-z0-9_-]{1,24}(/[A-Za-z0-9 _-]{1,24}){0,2}\\.(md|json|html|txt)",
+            content in any::<String>(),
+        ) {
+            let data_dir = tempfile::tempdir().unwrap();
+            write_data_file_in(data_dir.path(), &filename, &content, false, false, true).unwrap();
+            prop_assert_eq!(read_data_file_in(data_dir.path(), &filename).unwrap(), content.clone());
+
+            // Overwriting goes through the same temp-file rename and leaves no temp files
+            write_data_file_in(data_dir.path(), &filename, "", false, false, true).unwrap();
+            prop_assert_eq!(read_data_file_in(data_dir.path(), &filename).unwrap(), "");
+            let parent = data_dir.path().join(&filename).parent().unwrap().to_path_buf();
+            let leftovers = fs::read_dir(parent)
+                .unwrap()
+                .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+                .count();
+            prop_assert_eq!(leftovers, 0);
+        }
+    }
+
+    #[test]
+    fn urlencoding_decode_keeps_multibyte_sequences() {
+        assert_eq!(urlencoding_decode("Report%20abc123%2012345"), "Report abc123 12345");
+        assert_eq!(urlencoding_decode("%ED%95%9C%EA%B8%80.png"), "한글.png");
+        assert_eq!(urlencoding_decode("한글%20name.png"), "한글 name.png");
+        assert_eq!(urlencoding_decode("100%"), "100%");
+        assert_eq!(urlencoding_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn copy_html_with_images_handles_notion_export() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+
+        // Notion names the page and its asset folder "<title> <uuid>"
+        let page = "Report abc123 12345";
+        let assets = export.path().join(page);
+        fs::create_dir_all(&assets).unwrap();
+        fs::write(assets.join("image.png"), b"png").unwrap();
+        fs::write(assets.join("그림 1.png"), b"png2").unwrap();
+        let html = concat!(
+            "<html><body>",
+            "<img src=\"Report%20abc123%2012345/image.png\">",
+            "<figure><img src=\"Report%20abc123%2012345/%EA%B7%B8%EB%A6%BC%201.png\"></figure>",
+            "</body></html>",
+        );
+        let source = export.path().join(format!("{}.html", page));
+        fs::write(&source, html).unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(result.filename, "Report abc123 12345.html");
+        assert!(data_dir.path().join(&result.filename).is_file());
+        assert_eq!(result.copied_assets.len(), 2);
+        assert_eq!(fs::read(data_dir.path().join(page).join("image.png")).unwrap(), b"png");
+        assert_eq!(fs::read(data_dir.path().join(page).join("그림 1.png")).unwrap(), b"png2");
+    }
+
+    #[test]
+    fn copy_html_with_images_resolves_against_base_href() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("images")).unwrap();
+        fs::write(export.path().join("images/fig1.png"), b"fig").unwrap();
+        fs::write(export.path().join("fig2.png"), b"not under base").unwrap();
+        let source = export.path().join("paper.html");
+        fs::write(
+            &source,
+            "<head><base href=\"images/\"></head><img src=\"fig1.png\"><img src=\"fig2.png\">",
+        )
+        .unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(result.copied_assets, vec!["images/fig1.png".to_string()]);
+        assert_eq!(fs::read(data_dir.path().join("images/fig1.png")).unwrap(), b"fig");
+        assert!(!data_dir.path().join("fig2.png").exists());
+
+        // An absolute base URL falls back to the HTML file's own directory
+        fs::write(&source, "<base href=\"https://example.com/a/\"><img src=\"fig2.png\">").unwrap();
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+        assert_eq!(result.copied_assets, vec!["fig2.png".to_string()]);
+    }
+
+    /// Parallel asset copying for a 100-asset export shouldn't fall far behind a
+    /// plain sequential `fs::copy` loop. Timing-dependent, so run it explicitly with
+    /// `cargo test copy_html_with_images_benchmark -- --ignored`.
+    #[test]
+    #[ignore]
+    fn copy_html_with_images_benchmark() {
+        let export = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("assets")).unwrap();
+        let mut html = String::new();
+        for i in 0..100 {
+            fs::write(export.path().join(format!("assets/{}.png", i)), vec![i as u8; 512 * 1024]).unwrap();
+            html.push_str(&format!("<img src=\"assets/{}.png\">", i));
+        }
+        let source = export.path().join("export.html");
+        fs::write(&source, &html).unwrap();
+
+        let sequential_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(sequential_dir.path().join("assets")).unwrap();
+        let start = std::time::Instant::now();
+        for i in 0..100 {
+            let relative = format!("assets/{}.png", i);
+            fs::copy(export.path().join(&relative), sequential_dir.path().join(&relative)).unwrap();
+        }
+        let sequential = start.elapsed();
+
+        let parallel_dir = tempfile::tempdir().unwrap();
+        let start = std::time::Instant::now();
+        let result = copy_html_with_images_into(&source, parallel_dir.path(), false, None, false).unwrap();
+        let parallel = start.elapsed();
+
+        assert_eq!(result.copied_assets.len(), 100);
+        // Generous bound: the parallel path also parses the HTML and writes atomically
+        assert!(parallel < sequential * 3, "parallel {:?} vs sequential {:?}", parallel, sequential);
+    }
+
+    #[test]
+    fn copy_html_with_images_stops_on_iframe_cycles() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("frames")).unwrap();
+        fs::write(export.path().join("frames/fig.png"), b"fig").unwrap();
+        fs::write(
+            export.path().join("frames/b.html"),
+            "<img src=\"fig.png\"><iframe src=\"../a.html\"></iframe>",
+        )
+        .unwrap();
+        let source = export.path().join("a.html");
+        fs::write(&source, "<iframe src=\"frames/b.html\"></iframe>").unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+        assert!(result.copied_assets.is_empty());
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), true, None, false).unwrap();
+        assert_eq!(result.copied_assets, vec!["frames/b.html".to_string(), "frames/fig.png".to_string()]);
+        assert_eq!(fs::read(data_dir.path().join("frames/fig.png")).unwrap(), b"fig");
+    }
+
+    #[test]
+    fn copy_html_with_images_copies_picture_sources() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("img")).unwrap();
+        for name in ["wide.webp", "wide@2x.webp", "narrow.webp", "fallback.png"] {
+            fs::write(export.path().join("img").join(name), name).unwrap();
+        }
+        let source = export.path().join("paper.html");
+        fs::write(
+            &source,
+            concat!(
+                "<picture>",
+                "<source media=\"(min-width: 800px)\" srcset=\"img/wide.webp 1x, img/wide@2x.webp 2x\">",
+                "<source srcset=\"img/narrow.webp,https://cdn.example.com/n.webp 2x\">",
+                "<img src=\"img/fallback.png\" srcset=\"./img/narrow.webp 480w\">",
+                "</picture>",
+            ),
+        )
+        .unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(
+            result.copied_assets,
+            vec!["img/wide.webp", "img/wide@2x.webp", "img/fallback.png", "img/narrow.webp"]
+        );
+        let html = fs::read_to_string(data_dir.path().join("paper.html")).unwrap();
+        assert!(html.contains("srcset=\"img/narrow.webp 480w\""));
+    }
+
+    #[test]
+    fn copy_html_with_images_namespaces_assets_under_dest_subdir() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("images")).unwrap();
+        fs::write(export.path().join("images/fig1.png"), b"fig").unwrap();
+        fs::write(export.path().join("images/frame.html"), "<img src=\"fig1.png\">").unwrap();
+        let source = export.path().join("paper.html");
+        fs::write(
+            &source,
+            "<img src=\"images/fig1.png\"><iframe src=\"images/frame.html\"></iframe>",
+        )
+        .unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), true, Some("paper-a"), false).unwrap();
+
+        assert_eq!(result.copied_assets, vec!["paper-a/images/fig1.png", "paper-a/images/frame.html"]);
+        assert!(!data_dir.path().join("images").exists());
+        assert_eq!(
+            fs::read_to_string(data_dir.path().join("paper.html")).unwrap(),
+            "<img src=\"paper-a/images/fig1.png\"><iframe src=\"paper-a/images/frame.html\"></iframe>"
+        );
+        // The frame moved along with its image, so its reference still holds
+        assert_eq!(
+            fs::read_to_string(data_dir.path().join("paper-a/images/frame.html")).unwrap(),
+            "<img src=\"fig1.png\">"
+        );
+        assert!(copy_html_with_images_into(&source, data_dir.path(), false, Some("../out"), false).is_err());
+    }
+
+    #[test]
+    fn copy_html_with_images_copies_canvas_fallbacks() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("figs")).unwrap();
+        fs::write(export.path().join("figs/plot.png"), b"plot").unwrap();
+        let source = export.path().join("paper.html");
+        fs::write(
+            &source,
+            "<canvas id=\"c\" data-src=\"./figs/plot.png\"></canvas><canvas data-src=\"https://x.org/a.png\"></canvas>",
+        )
+        .unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(result.copied_assets, vec!["figs/plot.png"]);
+        assert_eq!(fs::read(data_dir.path().join("figs/plot.png")).unwrap(), b"plot");
+        let html = fs::read_to_string(data_dir.path().join("paper.html")).unwrap();
+        assert!(html.contains("<canvas id=\"c\" data-src=\"figs/plot.png\">"));
+    }
+
+    #[test]
+    fn copy_html_with_images_finds_src_after_lazy_loading_attrs() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("images")).unwrap();
+        fs::write(export.path().join("images/fig1.png"), b"fig1").unwrap();
+        fs::write(export.path().join("images/fig2.png"), b"fig2").unwrap();
+        fs::write(export.path().join("decoy.png"), b"decoy").unwrap();
+        let source = export.path().join("paper.html");
+        fs::write(
+            &source,
+            concat!(
+                "<img loading=\"lazy\" decoding=\"async\" src=\"images/fig1.png\">",
+                "<IMG LOADING=lazy SRC=./images/fig2.png>",
+                "<img loading='lazy' alt='see src=\"decoy.png\"' src='images/fig1.png'>",
+            ),
+        )
+        .unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(result.copied_assets, vec!["images/fig1.png", "images/fig2.png"]);
+        assert!(!data_dir.path().join("decoy.png").exists());
+        let html = fs::read_to_string(data_dir.path().join("paper.html")).unwrap();
+        assert!(html.contains("<IMG LOADING=lazy SRC=images/fig2.png>"));
+        assert!(html.contains("alt='see src=\"decoy.png\"' src='images/fig1.png'"));
+    }
+
+    // Windows can't create the unsanitized source name in the first place
+    #[cfg(unix)]
+    #[test]
+    fn copy_html_with_images_sanitizes_asset_names() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("figs: draft")).unwrap();
+        fs::write(export.path().join("figs: draft/fig?1.png"), b"fig").unwrap();
+        let source = export.path().join("paper.html");
+        fs::write(&source, "<img src=\"figs%3A%20draft/fig%3F1.png\">").unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(result.copied_assets, vec!["figs_ draft/fig_1.png"]);
+        assert_eq!(fs::read(data_dir.path().join("figs_ draft/fig_1.png")).unwrap(), b"fig");
+        let html = fs::read_to_string(data_dir.path().join("paper.html")).unwrap();
+        assert!(html.contains("src=\"figs_%20draft/fig_1.png\""), "{}", html);
+    }
+
+    #[test]
+    fn copy_html_with_images_dry_run_writes_nothing() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(export.path().join("images")).unwrap();
+        fs::write(export.path().join("images/fig1.png"), b"fig").unwrap();
+        let source = export.path().join("paper.html");
+        fs::write(&source, "<img src=\"./images/../images/fig1.png\"><img src=\"missing.png\">").unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, Some("paper-a"), true).unwrap();
+
+        assert!(result.dry_run);
+        assert_eq!(result.filename, "paper.html");
+        assert_eq!(result.copied_assets, vec!["paper-a/images/fig1.png"]);
+        assert_eq!(fs::read_dir(data_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn copy_html_with_images_transcodes_latin1_and_redeclares_charset() {
+        let export = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::write(export.path().join("fig.png"), b"png").unwrap();
+        // "Résumé" in Latin-1: é is the single byte 0xE9, invalid as UTF-8
+        let mut html = b"<html><head><meta charset=\"windows-1252\">".to_vec();
+        html.extend_from_slice(b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=ISO-8859-1\">");
+        html.extend_from_slice(b"</head><body><h1>R\xe9sum\xe9</h1><img src=\"fig.png\"></body></html>");
+        let source = export.path().join("latin1.html");
+        fs::write(&source, &html).unwrap();
+
+        let result = copy_html_with_images_into(&source, data_dir.path(), false, None, false).unwrap();
+
+        assert_eq!(result.copied_assets, vec!["fig.png".to_string()]);
+        let copied = fs::read_to_string(data_dir.path().join("latin1.html")).unwrap();
+        assert_eq!(
+            copied,
+            concat!(
+                "<html><head><meta charset=\"UTF-8\">",
+                "<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">",
+                "</head><body><h1>Résumé</h1><img src=\"fig.png\"></body></html>",
+            )
+        );
+    }
+
+    #[test]
+    fn verify_all_asset_links_reports_broken_references() {
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(data_dir.path().join("paper-a/images")).unwrap();
+        fs::write(data_dir.path().join("paper-a/images/fig1.png"), b"fig").unwrap();
+        fs::write(data_dir.path().join("paper-a/style.css"), b"").unwrap();
+        fs::write(
+            data_dir.path().join("paper-a/index.html"),
+            concat!(
+                "<link rel=\"stylesheet\" href=\"style.css?v=2\"><style>body { background: url('bg.png') }</style>",
+                "<img src=\"images/fig1.png\"><img src=\"images/fig2.png\"><a href=\"#sec-1\">1</a>",
+                "<a href=\"mailto:a@b.org\">mail</a><div style=\"background-image:url(images/fig%201.png)\"></div>",
+                "<a href=\"../../outside.pdf\">pdf</a>",
+            ),
+        )
+        .unwrap();
+        fs::write(data_dir.path().join("ok.html"), "<img src=\"paper-a/images/fig1.png\">").unwrap();
+
+        let report = verify_all_asset_links_in(data_dir.path()).unwrap();
+
+        assert_eq!(report.papers_checked, 2);
+        assert_eq!(report.links_checked, 7);
+        assert_eq!(report.papers.len(), 1);
+        assert_eq!(report.papers[0].paper, "paper-a/index.html");
+        let broken: Vec<(&str, Option<&str>)> = report.papers[0]
+            .broken
+            .iter()
+            .map(|b| (b.reference.as_str(), b.resolved.as_deref()))
+            .collect();
+        assert_eq!(
+            broken,
+            vec![
+                ("images/fig2.png", Some("paper-a/images/fig2.png")),
+                ("images/fig%201.png", Some("paper-a/images/fig 1.png")),
+                ("../../outside.pdf", None),
+                ("bg.png", Some("paper-a/bg.png")),
+            ]
+        );
+    }
 }
@@ -100,8 +100,8 @@ export const ExplorerPanel: React.FC<ExplorerPanelProps> = ({
                         let filename: string;
                         try {
                             filename = isHtml 
-                                ? await invoke('copy_html_with_images', { sourcePath: absolutePath })
-                                : await invoke('copy_file_to_data', { sourcePath: absolutePath });
+                                ? (await invoke<{ filename: string }>('copy_html_with_images', { sourcePath: absolutePath })).filename
+                                : (await invoke<{ filename: string }>('copy_file_to_data', { sourcePath: absolutePath })).filename;
                         } catch (copyErr) {
                             console.error('[Import] File copy failed:', copyErr);
                             toast.error('파일 복사 실패', { description: String(copyErr) });
@@ -119,8 +119,8 @@ export const ExplorerPanel: React.FC<ExplorerPanelProps> = ({
                             try {
                                 const { readTextFile } = await import('@tauri-apps/plugin-fs');
                                 const content = await readTextFile(absolutePath);
-                                const filename = absolutePath.replace(/\\/g, '/').split('/').pop() || 'imported.html';
-                                await storageManager.writeFile(filename, content);
+                                const name = absolutePath.replace(/\\/g, '/').split('/').pop() || 'imported.html';
+                                const filename = await storageManager.writeFile(name, content);
                                 await LibraryManager.addItem(filename, storageManager);
                                 toast.success(`"${filename}" 문서가 추가되었습니다`);
                                 writeSuccess = true;
@@ -149,13 +149,13 @@ export const ExplorerPanel: React.FC<ExplorerPanelProps> = ({
 
     // Optional Storage Manager for persistence 
     // (Ideally specific props, but for now we try global access or simple fetch)
-    const uploadToDevServer = async (file: File): Promise<boolean> => {
+    // Resolves to the path the file was saved under, or null if it couldn't be saved
+    const uploadToDevServer = async (file: File): Promise<string | null> => {
         // 1. Try StorageManager (Tauri / Persistence)
         if (storageManager && storageManager.isConnected) {
             try {
                 const text = await file.text();
-                await storageManager.writeFile(file.name, text);
-                return true;
+                return await storageManager.writeFile(file.name, text);
             } catch(e) {
                 console.warn("StorageManager write failed", e);
             }
@@ -169,9 +169,9 @@ export const ExplorerPanel: React.FC<ExplorerPanelProps> = ({
                 method: 'POST',
                 body: buffer
             });
-            return res.ok;
+            return res.ok ? file.name : null;
         } catch (e) {
-            return false;
+            return null;
         }
     };
 
@@ -186,7 +186,7 @@ export const ExplorerPanel: React.FC<ExplorerPanelProps> = ({
                     
                     if (uploaded) {
                         // If uploaded, use real path
-                        const realPath = file.name;
+                        const realPath = uploaded;
                         await LibraryManager.addItem(realPath, storageManager); 
                         toast.success(`${file.name} saved to disk`);
                     } else {
@@ -210,7 +210,7 @@ export const ExplorerPanel: React.FC<ExplorerPanelProps> = ({
                 const uploaded = await uploadToDevServer(file);
                 
                 if (uploaded) {
-                    const realPath = file.name;
+                    const realPath = uploaded;
                     await LibraryManager.addItem(realPath, storageManager);
                     toast.success(`${file.name} saved to disk`);
                 } else {
@@ -17,6 +17,8 @@ import {
 } from '@tauri-apps/plugin-fs';
 
 const CACHE_FOLDER = 'cache';
+// Error the Rust backend returns while the library is locked; never fall back to plugin-fs on it
+const LOCKED_ERROR = 'Locked';
 const DEFAULT_TAURI_DIR = 'paper-reader-data';
 
 interface StorageConfig {
@@ -204,6 +206,7 @@ export class LocalStorageManager {
                         extensions.some(ext => f.toLowerCase().endsWith(ext.toLowerCase()))
                     );
                 } catch (rustErr) {
+                    if (rustErr === LOCKED_ERROR) throw rustErr;
                     console.warn('[Storage] Rust listFiles failed, trying JS:', rustErr);
                 }
             }
@@ -307,6 +310,7 @@ export class LocalStorageManager {
                     const { invoke } = await import('@tauri-apps/api/core');
                     return await invoke('read_data_file', { filename: normalizePath(path) });
                 } catch (rustErr) {
+                    if (rustErr === LOCKED_ERROR) throw rustErr;
                     console.warn('[Storage] Rust read failed, trying JS:', rustErr);
                 }
             }
@@ -343,7 +347,8 @@ export class LocalStorageManager {
         return localStorage.getItem(`data:${path}`) || localStorage.getItem(`cached-file:${path}`);
     }
 
-    async writeFile(path: string, content: string): Promise<void> {
+    /** Returns the path the content was stored under; the Rust backend sanitizes names */
+    async writeFile(path: string, content: string): Promise<string> {
         await this.ensureReady();
 
         if (this.config.useTauri) {
@@ -351,10 +356,11 @@ export class LocalStorageManager {
             if (this._useRustBackend && !isAbsolutePath(path)) {
                 try {
                     const { invoke } = await import('@tauri-apps/api/core');
-                    await invoke('write_data_file', { filename: normalizePath(path), content });
-                    console.log(`[Storage] writeFile via Rust OK: ${path}`);
-                    return;
+                    const stored: string = await invoke('write_data_file', { filename: normalizePath(path), content });
+                    console.log(`[Storage] writeFile via Rust OK: ${stored}`);
+                    return stored;
                 } catch (rustErr) {
+                    if (rustErr === LOCKED_ERROR) throw rustErr;
                     console.warn('[Storage] Rust write failed, trying JS:', rustErr);
                 }
             }
@@ -378,13 +384,13 @@ export class LocalStorageManager {
                 console.error('[LocalStorageManager] Tauri write failed', error);
                 throw error;
             }
-            return;
+            return path;
         }
 
         if (this.config.useDevServer) {
             try {
                 const clean = normalizePath(path);
-                if (!clean) return;
+                if (!clean) return path;
 
                 await fetch(`/api/fs/file?path=${encodeURIComponent(clean)}`, {
                     method: 'POST',
@@ -393,12 +399,13 @@ export class LocalStorageManager {
             } catch (error) {
                 console.error('[LocalStorageManager] Dev write failed', error);
             }
-            return;
+            return path;
         }
 
         if (path.endsWith('.json') || path.endsWith('.md')) {
             localStorage.setItem(`data:${path}`, content);
         }
+        return path;
     }
 
     async deleteFile(path: string): Promise<void> {
@@ -411,6 +418,7 @@ export class LocalStorageManager {
                     await invoke('delete_data_file', { filename: normalizePath(path) });
                     return;
                 } catch (rustErr) {
+                    if (rustErr === LOCKED_ERROR) throw rustErr;
                     console.warn('[Storage] Rust delete failed, trying JS:', rustErr);
                 }
             }
@@ -540,7 +548,8 @@ export class LocalStorageManager {
                     }
                     const blob = new Blob([bytes], { type: inferMimeType(clean) });
                     return URL.createObjectURL(blob);
-                } catch {
+                } catch (rustErr) {
+                    if (rustErr === LOCKED_ERROR) throw rustErr;
                     // Fall through to JS plugin-fs
                 }
             }
@@ -624,7 +633,7 @@ export class LocalStorageManager {
     }
 
     // Deprecated helpers for flat KV
-    async save(key: string, value: string): Promise<void> { return this.writeFile(key, value); }
+    async save(key: string, value: string): Promise<void> { await this.writeFile(key, value); }
     async load(key: string): Promise<string | null> { return this.readFile(key); }
     async loadImage(filename: string): Promise<string | null> { return this.loadFileAsUrl(filename); }
     getStorageInfo(): string { return this.getPathInfo(); }
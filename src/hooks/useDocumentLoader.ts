@@ -106,11 +106,11 @@ export function useDocumentLoader({
                     const { readTextFile } = await import('@tauri-apps/plugin-fs');
                     const content = await readTextFile(effectiveFilePath);
                     if (content) {
-                        await storageManager.writeFile(filename, content);
-                        console.log(`[Migration] Copied absolute-path file to data dir: ${filename}`);
-                        await LibraryManager.updateMetadata(activeFile.id, { filePath: filename }, storageManager);
+                        const stored = await storageManager.writeFile(filename, content);
+                        console.log(`[Migration] Copied absolute-path file to data dir: ${stored}`);
+                        await LibraryManager.updateMetadata(activeFile.id, { filePath: stored }, storageManager);
                         DocumentSessionManager.removeSession(activeFile.filePath);
-                        effectiveFilePath = filename;
+                        effectiveFilePath = stored;
                     }
                 } catch (migrationError) {
                     console.warn('[Migration] Original file not found, searching data dir...', migrationError);